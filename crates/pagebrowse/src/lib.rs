@@ -1,36 +1,190 @@
 use std::{
-    borrow::Borrow, collections::HashMap, fmt::format, path::PathBuf, process::Stdio, sync::Arc,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::{ExitStatus, Stdio},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Weak,
+    },
+    task::{Context, Poll},
+    time::Duration,
 };
 
 use base64::{engine::general_purpose, Engine};
+use futures_core::Stream;
+use pagebrowse_types::framing;
+pub use pagebrowse_types::{
+    Capability, ClipRect, ConsoleLevel, ConsoleMessage, Cookie, DialogKind, Framing, ImageFormat,
+    InProcessConnection, ManagerInfo, NavigationResult, OriginStorage, PageEvent, SameSite,
+    ScreenshotOptions, StorageState, WaitUntil, WindowReset, PROTOCOL_VERSION,
+};
 use pagebrowse_types::{
     InitializationParams, PBRequest, PBRequestPayload, PBResponse, PBResponsePayload,
 };
+use serde::{de::DeserializeOwned, Serialize};
 use thiserror::Error;
 use tokio::{
-    io::AsyncBufReadExt,
-    io::{AsyncReadExt, AsyncWriteExt, BufReader},
-    process::{Child, Command},
-    sync::{
-        broadcast,
-        mpsc::{error::SendError, unbounded_channel, UnboundedReceiver, UnboundedSender},
-        Mutex,
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufReader,
     },
+    net::TcpStream,
+    process::{Child, ChildStderr, Command},
+    sync::{mpsc, oneshot, watch, Mutex},
+    time::timeout,
 };
 
+/// How long the manager gets to exit after closing its stdout before it is killed
+const EXIT_GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// How long a manager gets to start and answer the handshake, unless the builder says otherwise
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many times in a row a manager that fails to start is restarted before giving up
+const MAX_RESTART_ATTEMPTS: u32 = 5;
+/// How long to wait before retrying a failed restart, doubling after each failure
+const RESTART_BACKOFF: Duration = Duration::from_millis(200);
+/// How much of the end of the manager's stderr is kept to report when it exits
+const STDERR_CAPTURE_LIMIT: usize = 16 * 1024;
+
+/// Returns the page's origin and its localStorage, for `PagebrowserWindow::storage_state`
+const CAPTURE_LOCAL_STORAGE_SCRIPT: &str = "if (location.origin === \"null\") return null;\n\
+    return { origin: location.origin, local_storage: Object.fromEntries(Object.entries(localStorage)) };";
+
 #[derive(Error, Debug)]
 pub enum PagebrowseError {
     #[error("unknown error")]
     Unknown,
     #[error("no manager available")]
     NoManager,
+    #[error("manager returned an error: {message}")]
+    ManagerError {
+        message: String,
+        original_message: Option<String>,
+    },
+    #[error("unexpected response from the manager: {0:?}")]
+    UnexpectedResponse(PBResponsePayload),
+    #[error("manager exited ({}){}", describe_status(.status), describe_stderr(.stderr))]
+    ManagerExited {
+        status: Option<ExitStatus>,
+        stderr: String,
+    },
+    #[error(
+        "manager exited ({}) and couldn't be restarted: {error}{}",
+        describe_status(.status),
+        describe_stderr(.stderr)
+    )]
+    RestartFailed {
+        status: Option<ExitStatus>,
+        stderr: String,
+        /// Why the last attempt to restart it failed
+        error: String,
+    },
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    #[error("timed out waiting for the manager")]
+    Timeout,
+    #[error("failed to communicate with the manager: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("script threw an exception: {message}")]
+    ScriptError {
+        message: String,
+        line: Option<u32>,
+        column: Option<u32>,
+        stack: Option<String>,
+    },
+    #[error("invalid script arguments: {0}")]
+    InvalidArguments(String),
+    #[error("unable to deserialize script output: {0}")]
+    InvalidOutput(serde_json::Error),
+    #[error("invalid storage state: {0}")]
+    InvalidStorageState(serde_json::Error),
+    #[error("invalid manager address `{0}`, expected unix:<path> or tcp:<host>:<port>")]
+    InvalidAddress(String),
+    #[error(
+        "manager {} speaks protocol {}, but this client speaks protocol {PROTOCOL_VERSION}; \
+        install a pagebrowse_manager that matches this version of pagebrowse",
+        .version.as_deref().unwrap_or("from an older release"),
+        describe_protocol(.protocol_version)
+    )]
+    IncompatibleManager {
+        version: Option<String>,
+        protocol_version: Option<u32>,
+    },
+}
+
+fn describe_status(status: &Option<ExitStatus>) -> String {
+    match status {
+        Some(status) => status.to_string(),
+        None => "unknown status".into(),
+    }
+}
+
+fn describe_protocol(protocol_version: &Option<u32>) -> String {
+    match protocol_version {
+        Some(protocol_version) => protocol_version.to_string(),
+        None => "0".into(),
+    }
+}
+
+/// Checks the manager's answer to `Initialize`, returning what it said about itself
+fn check_handshake(response: PBResponsePayload) -> Result<ManagerInfo, PagebrowseError> {
+    match response {
+        PBResponsePayload::Initialized { manager, .. }
+            if manager.protocol_version == PROTOCOL_VERSION =>
+        {
+            Ok(manager)
+        }
+        PBResponsePayload::Initialized { manager, .. } => {
+            Err(PagebrowseError::IncompatibleManager {
+                version: Some(manager.version),
+                protocol_version: Some(manager.protocol_version),
+            })
+        }
+        // Managers from before the handshake don't describe themselves
+        PBResponsePayload::OperationComplete => Err(PagebrowseError::IncompatibleManager {
+            version: None,
+            protocol_version: None,
+        }),
+        other => Err(PagebrowseError::UnexpectedResponse(other)),
+    }
+}
+
+fn describe_stderr(stderr: &str) -> String {
+    if stderr.is_empty() {
+        String::new()
+    } else {
+        format!(", stderr:\n{stderr}")
+    }
+}
+
+/// What to do when the manager process exits unexpectedly.
+/// Windows that belonged to an exited manager stay unusable either way.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum RestartPolicy {
+    /// Fail all pending and future commands
+    #[default]
+    Never,
+    /// Respawn and reinitialize the manager, at most this many times
+    Limited(u32),
+    /// Always respawn and reinitialize the manager,
+    /// unless it fails to start several times in a row
+    Always,
 }
 
 pub struct PagebrowseBuilder {
     pool_size: usize,
     visible: bool,
     init_script: Option<String>,
+    window_reset: WindowReset,
+    isolated_sessions: bool,
+    storage_state: Option<PathBuf>,
     manager_path: PathBuf,
+    connect: Option<String>,
+    in_process: Option<InProcessConnector>,
+    restart_policy: RestartPolicy,
+    default_timeout: Option<Duration>,
+    startup_timeout: Duration,
 }
 
 impl PagebrowseBuilder {
@@ -39,7 +193,15 @@ impl PagebrowseBuilder {
             pool_size,
             visible: false,
             init_script: None,
+            window_reset: WindowReset::default(),
+            isolated_sessions: false,
+            storage_state: None,
             manager_path: "pagebrowse_manager".into(),
+            connect: None,
+            in_process: None,
+            restart_policy: RestartPolicy::Never,
+            default_timeout: None,
+            startup_timeout: STARTUP_TIMEOUT,
         }
     }
 
@@ -53,240 +215,1864 @@ impl PagebrowseBuilder {
         self
     }
 
+    /// Connect to a manager that is already running with `--listen`, rather than spawning one,
+    /// e.g. `unix:/tmp/pagebrowse.sock` or `tcp:127.0.0.1:9000`.
+    /// The first consumer to connect configures the manager's pool, so later consumers' pool options are ignored.
+    pub fn connect(mut self, addr: impl Into<String>) -> Self {
+        self.connect = Some(addr.into());
+        self
+    }
+
+    /// Talk to a manager hosted in this process over in-memory channels, rather than spawning one,
+    /// e.g. with `pagebrowse_manager::Manager::connector`. `connect` is called again if the
+    /// browser restarts. Takes precedence over `connect`.
+    pub fn in_process(
+        mut self,
+        connect: impl Fn() -> InProcessConnection + Send + Sync + 'static,
+    ) -> Self {
+        self.in_process = Some(Arc::new(connect));
+        self
+    }
+
     pub fn init_script(mut self, init_script: String) -> Self {
         self.init_script = Some(init_script);
         self
     }
 
+    /// How much browsing state is cleared from a window after it is dropped,
+    /// before `get_window` hands it out again
+    pub fn window_reset(mut self, window_reset: WindowReset) -> Self {
+        self.window_reset = window_reset;
+        self
+    }
+
+    /// Give each window its own ephemeral cookies, storage and caches,
+    /// so that concurrent windows can't see each other's sessions
+    pub fn isolated_sessions(mut self, isolated_sessions: bool) -> Self {
+        self.isolated_sessions = isolated_sessions;
+        self
+    }
+
+    /// Load the cookies and localStorage saved by `PagebrowserWindow::save_storage_state`
+    /// into every window, e.g. to start each window already logged in
+    pub fn storage_state(mut self, path: impl Into<PathBuf>) -> Self {
+        self.storage_state = Some(path.into());
+        self
+    }
+
+    pub fn restart_policy(mut self, restart_policy: RestartPolicy) -> Self {
+        self.restart_policy = restart_policy;
+        self
+    }
+
+    /// How long any command waits for the manager before failing with `PagebrowseError::Timeout`.
+    /// Windows can override this with `PagebrowserWindow::with_timeout`.
+    pub fn default_timeout(mut self, default_timeout: Duration) -> Self {
+        self.default_timeout = Some(default_timeout);
+        self
+    }
+
+    /// How long the manager gets to start and answer the handshake, when the browser is built
+    /// and each time it restarts. Creating the pool's webviews can take a while, so this
+    /// is separate from `default_timeout` and defaults to 30 seconds.
+    pub fn startup_timeout(mut self, startup_timeout: Duration) -> Self {
+        self.startup_timeout = startup_timeout;
+        self
+    }
+
     pub async fn build(self) -> Result<Pagebrowser, PagebrowseError> {
         let PagebrowseBuilder {
             pool_size,
             visible,
             init_script,
+            window_reset,
+            isolated_sessions,
+            storage_state,
             manager_path,
+            connect,
+            in_process,
+            restart_policy,
+            default_timeout,
+            startup_timeout,
         } = self;
 
-        let (tx_response, rx_response) = broadcast::channel::<PBResponse>(100);
+        let source = match (in_process, connect) {
+            (Some(connector), _) => ManagerSource::InProcess(connector),
+            (None, Some(addr)) => ManagerSource::Connect(addr.parse()?),
+            (None, None) => ManagerSource::Spawn(manager_path),
+        };
 
-        let mut command = Command::new(manager_path);
-        command.kill_on_drop(true);
-        command.stdin(Stdio::piped()).stdout(Stdio::piped());
+        let storage_state = match storage_state {
+            Some(path) => Some(
+                serde_json::from_slice(&tokio::fs::read(path).await?)
+                    .map_err(PagebrowseError::InvalidStorageState)?,
+            ),
+            None => None,
+        };
 
-        let mut child = command.spawn().map_err(|_| PagebrowseError::NoManager)?;
+        let params = InitializationParams {
+            pool_size,
+            visible,
+            init_script,
+            window_reset,
+            isolated_sessions,
+            storage_state,
+            // Managers that don't know about length-prefixed framing will stick to base64
+            framing: Framing::LengthPrefixed,
+            protocol_version: Some(PROTOCOL_VERSION),
+        };
 
-        let stdout = child.stdout.take().unwrap();
-        tokio::spawn(async move {
-            let mut reader = BufReader::new(stdout);
+        let streams = open_manager(&source).await?;
 
-            loop {
-                let mut buf = vec![];
-                reader.read_until(b',', &mut buf).await.unwrap();
+        let browser = Pagebrowser {
+            inner: Arc::new_cyclic(|browser| PagebrowserInner {
+                latest_message_id: AtomicU32::new(0),
+                default_timeout,
+                startup_timeout,
+                state: Mutex::new(ManagerState {
+                    connection: start_connection(streams, 0, browser.clone()),
+                    latest_generation: 0,
+                    running: true,
+                    last_exit: None,
+                    restart_error: None,
+                    restarts: 0,
+                    source,
+                    params: params.clone(),
+                    manager: None,
+                    restart_policy,
+                }),
+            }),
+        };
 
-                if buf.pop().is_none() {
-                    // EOF Reached
-                    // TODO: Handle the manager dying
-                    break;
-                }
+        let response = browser
+            .send_command_to(
+                None,
+                PBRequestPayload::Initialize(params),
+                Some(startup_timeout),
+            )
+            .await?;
+        browser.inner.state.lock().await.manager = Some(check_handshake(response)?);
 
-                let Ok(decoded) = general_purpose::STANDARD.decode(&buf) else {
-                    let msg = std::str::from_utf8(&buf).unwrap();
-                    panic!("Received garbled base64 from the manager: {msg}");
-                };
+        Ok(browser)
+    }
+}
 
-                match serde_json::from_slice::<PBResponse>(&decoded) {
-                    Ok(msg) => {
-                        tx_response.send(msg).expect("Broadcast channel is open");
-                    }
-                    Err(e) => {
-                        panic!("Received garbled json from the manager");
-                    }
-                }
-            }
-        });
+#[derive(Clone, Debug)]
+struct ManagerExit {
+    status: Option<ExitStatus>,
+    stderr: String,
+}
 
-        let browser = Pagebrowser::new(child, rx_response);
+impl From<ManagerExit> for PagebrowseError {
+    fn from(exit: ManagerExit) -> Self {
+        PagebrowseError::ManagerExited {
+            status: exit.status,
+            stderr: exit.stderr,
+        }
+    }
+}
 
-        browser
-            .send_command(PBRequestPayload::Initialize(InitializationParams {
-                pool_size,
-                visible,
-                init_script,
-            }))
-            .await
-            .map_err(|_| PagebrowseError::Unknown)?;
+/// Why a manager process can no longer answer requests
+#[derive(Clone, Debug)]
+enum ConnectionClosed {
+    /// The manager sent something unreadable, and is being stopped
+    Garbled(String),
+    Exited(ManagerExit),
+}
 
-        Ok(browser)
+impl ConnectionClosed {
+    fn error(&self) -> PagebrowseError {
+        match self {
+            ConnectionClosed::Garbled(message) => PagebrowseError::Protocol(message.clone()),
+            ConnectionClosed::Exited(exit) => exit.clone().into(),
+        }
     }
 }
 
-struct PagebrowserInner {
-    child: Child,
-    latest_message_id: u32,
-    rx_response: broadcast::Receiver<PBResponse>,
-}
+type ResponseResult = Result<PBResponsePayload, PagebrowseError>;
 
-#[derive(Clone)]
-pub struct Pagebrowser {
-    inner: Arc<Mutex<PagebrowserInner>>,
+enum PendingState {
+    Open(HashMap<u32, oneshot::Sender<ResponseResult>>),
+    Closed(ConnectionClosed),
 }
 
-impl Pagebrowser {
-    async fn send_command(
-        &self,
-        command: PBRequestPayload,
-    ) -> Result<PBResponsePayload, PagebrowseError> {
-        let (this_message_id, mut rxer) = {
-            let mut inner = self.inner.lock().await;
-            let rxer = inner.rx_response.resubscribe();
+/// The requests sent to one manager process that are still waiting on a response
+struct PendingResponses {
+    state: std::sync::Mutex<PendingState>,
+}
 
-            let this_message_id = inner.latest_message_id;
-            let request = PBRequest {
-                message_id: Some(this_message_id),
-                payload: command,
-            };
-            inner.latest_message_id += 1;
+impl PendingResponses {
+    fn new() -> Self {
+        Self {
+            state: std::sync::Mutex::new(PendingState::Open(HashMap::new())),
+        }
+    }
 
-            let encoded = general_purpose::STANDARD.encode(serde_json::to_vec(&request).unwrap());
+    fn register(
+        &self,
+        message_id: u32,
+    ) -> Result<oneshot::Receiver<ResponseResult>, PagebrowseError> {
+        let mut state = self.state.lock().expect("Pending lock is not poisoned");
+        match &mut *state {
+            PendingState::Open(waiting) => {
+                let (tx, rx) = oneshot::channel();
+                waiting.insert(message_id, tx);
+                Ok(rx)
+            }
+            PendingState::Closed(reason) => Err(reason.error()),
+        }
+    }
 
-            if let Some(stdin) = inner.child.stdin.as_mut() {
-                stdin.write_all(encoded.as_bytes()).await.unwrap();
-                stdin.write(b",").await.unwrap();
-                stdin.flush().await.unwrap();
+    fn resolve(&self, message_id: u32, result: ResponseResult) {
+        let mut state = self.state.lock().expect("Pending lock is not poisoned");
+        if let PendingState::Open(waiting) = &mut *state {
+            if let Some(tx) = waiting.remove(&message_id) {
+                // The caller may have stopped waiting, which is fine
+                _ = tx.send(result);
             }
+        }
+    }
 
-            (this_message_id, rxer)
-        };
+    /// Stops waiting on a request, returning whether it was still pending
+    fn forget(&self, message_id: u32) -> bool {
+        let mut state = self.state.lock().expect("Pending lock is not poisoned");
+        match &mut *state {
+            PendingState::Open(waiting) => waiting.remove(&message_id).is_some(),
+            PendingState::Closed(_) => false,
+        }
+    }
 
-        while let Ok(response) = rxer.recv().await {
-            if response.message_id == Some(this_message_id) {
-                return Ok(response.payload);
+    /// Fails every pending request, and any registered after this point
+    fn close(&self, reason: ConnectionClosed) {
+        let mut state = self.state.lock().expect("Pending lock is not poisoned");
+        let previous = std::mem::replace(&mut *state, PendingState::Closed(reason.clone()));
+        if let PendingState::Open(waiting) = previous {
+            for (_, tx) in waiting {
+                _ = tx.send(Err(reason.error()));
             }
         }
-
-        Err(PagebrowseError::Unknown)
     }
 }
 
-impl Pagebrowser {
-    pub fn new(child: Child, rx_response: broadcast::Receiver<PBResponse>) -> Self {
+/// Forwards the page events the manager pushes to each window's event streams
+struct EventSubscribers {
+    windows: std::sync::Mutex<HashMap<u32, Vec<mpsc::UnboundedSender<PageEvent>>>>,
+}
+
+impl EventSubscribers {
+    fn new() -> Self {
         Self {
-            inner: Arc::new(Mutex::new(PagebrowserInner {
-                child,
-                latest_message_id: 0,
-                rx_response,
-            })),
+            windows: std::sync::Mutex::new(HashMap::new()),
         }
     }
 
-    pub async fn get_window(&self) -> Result<PagebrowserWindow, PagebrowseError> {
-        let window_response = self.send_command(PBRequestPayload::NewWindow).await?;
+    fn subscribe(&self, window_id: u32) -> mpsc::UnboundedReceiver<PageEvent> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut windows = self.windows.lock().expect("Event lock is not poisoned");
+        windows.entry(window_id).or_default().push(tx);
+        rx
+    }
 
-        let PBResponsePayload::NewWindowCreated { id } = window_response else {
-            return Err(PagebrowseError::Unknown);
-        };
+    fn publish(&self, window_id: u32, event: PageEvent) {
+        let mut windows = self.windows.lock().expect("Event lock is not poisoned");
+        if let Some(subscribers) = windows.get_mut(&window_id) {
+            // Drop any streams that are no longer being read
+            subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        }
+    }
 
-        Ok(PagebrowserWindow {
-            id,
-            browser: self.clone(),
+    /// Ends the streams for a window
+    fn remove(&self, window_id: u32) {
+        let mut windows = self.windows.lock().expect("Event lock is not poisoned");
+        windows.remove(&window_id);
+    }
+
+    /// Ends every stream
+    fn close(&self) {
+        let mut windows = self.windows.lock().expect("Event lock is not poisoned");
+        windows.clear();
+    }
+}
+
+/// A running manager process
+struct ManagerConnection {
+    /// Incremented each time the manager is restarted
+    generation: u32,
+    tx_request: mpsc::UnboundedSender<PBRequest>,
+    pending: Arc<PendingResponses>,
+    events: Arc<EventSubscribers>,
+}
+
+impl ManagerConnection {
+    fn send(
+        &self,
+        request: PBRequest,
+    ) -> Result<oneshot::Receiver<ResponseResult>, PagebrowseError> {
+        let message_id = request
+            .message_id
+            .expect("Outbound requests have a message ID");
+        let rx = self.pending.register(message_id)?;
+
+        if self.tx_request.send(request).is_err() {
+            self.pending.resolve(
+                message_id,
+                Err(PagebrowseError::Protocol(
+                    "Manager writer has stopped".into(),
+                )),
+            );
+        }
+
+        Ok(rx)
+    }
+}
+
+async fn receive_response(rx: oneshot::Receiver<ResponseResult>) -> ResponseResult {
+    rx.await.unwrap_or_else(|_| {
+        Err(PagebrowseError::ManagerExited {
+            status: None,
+            stderr: String::new(),
         })
+    })
+}
+
+/// A manager that is already running with `--listen`
+#[derive(Clone, Debug)]
+enum ManagerAddr {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl FromStr for ManagerAddr {
+    type Err = PagebrowseError;
+
+    fn from_str(addr: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = addr.strip_prefix("unix:").filter(|path| !path.is_empty()) {
+            return Ok(ManagerAddr::Unix(path.into()));
+        }
+        if let Some(host) = addr.strip_prefix("tcp:").filter(|host| !host.is_empty()) {
+            return Ok(ManagerAddr::Tcp(host.into()));
+        }
+
+        Err(PagebrowseError::InvalidAddress(addr.into()))
     }
 }
 
-pub struct PagebrowserWindow {
-    id: u32,
-    browser: Pagebrowser,
+type InProcessConnector = Arc<dyn Fn() -> InProcessConnection + Send + Sync>;
+
+/// Where the browser gets a manager from, both initially and on restart
+enum ManagerSource {
+    Spawn(PathBuf),
+    Connect(ManagerAddr),
+    InProcess(InProcessConnector),
 }
 
-impl Drop for PagebrowserWindow {
-    fn drop(&mut self) {
-        let window_id = self.id;
-        let browser_ref = self.browser.clone();
+type ManagerReader = Box<dyn AsyncRead + Send + Unpin>;
+type ManagerWriter = Box<dyn AsyncWrite + Send + Unpin>;
 
-        tokio::spawn(async move {
-            let response = browser_ref
-                .send_command(PBRequestPayload::ReleaseWindow { window_id })
-                .await
-                .expect("should be able to close windows");
+/// How to talk to a manager, and its process if we spawned it
+struct ManagerStreams {
+    process: Option<ManagerProcess>,
+    transport: ManagerTransport,
+}
 
-            match response {
-                PBResponsePayload::OperationComplete => {}
-                _ => panic!("Errored releasing a Pagebrowse window"),
-            }
-        });
+enum ManagerTransport {
+    /// Framed messages over a pipe or socket
+    Streams {
+        writer: ManagerWriter,
+        reader: ManagerReader,
+    },
+    /// Messages passed as they are to a manager in this process
+    InProcess(InProcessConnection),
+}
+
+struct ManagerProcess {
+    child: Child,
+    stderr: ChildStderr,
+}
+
+async fn open_manager(source: &ManagerSource) -> Result<ManagerStreams, PagebrowseError> {
+    match source {
+        ManagerSource::Spawn(manager_path) => spawn_manager(manager_path),
+        ManagerSource::Connect(addr) => connect_manager(addr).await,
+        ManagerSource::InProcess(connect) => Ok(ManagerStreams {
+            process: None,
+            transport: ManagerTransport::InProcess(connect()),
+        }),
     }
 }
 
-impl PagebrowserWindow {
-    pub async fn navigate(&self, url: String, wait_for_load: bool) -> Result<(), PagebrowseError> {
-        let response = self
-            .browser
-            .send_command(PBRequestPayload::Navigate {
-                window_id: self.id,
-                url,
-                wait_for_load,
-            })
-            .await?;
+async fn connect_manager(addr: &ManagerAddr) -> Result<ManagerStreams, PagebrowseError> {
+    let (reader, writer): (ManagerReader, ManagerWriter) = match addr {
+        #[cfg(unix)]
+        ManagerAddr::Unix(path) => {
+            let (reader, writer) = tokio::net::UnixStream::connect(path).await?.into_split();
+            (Box::new(reader), Box::new(writer))
+        }
+        #[cfg(not(unix))]
+        ManagerAddr::Unix(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unix sockets are not supported on this platform",
+            )
+            .into())
+        }
+        ManagerAddr::Tcp(host) => {
+            let (reader, writer) = TcpStream::connect(host.as_str()).await?.into_split();
+            (Box::new(reader), Box::new(writer))
+        }
+    };
 
-        match response {
-            PBResponsePayload::OperationComplete => Ok(()),
-            _ => Err(PagebrowseError::Unknown),
+    Ok(ManagerStreams {
+        process: None,
+        transport: ManagerTransport::Streams { writer, reader },
+    })
+}
+
+fn spawn_manager(manager_path: &PathBuf) -> Result<ManagerStreams, PagebrowseError> {
+    let mut command = Command::new(manager_path);
+    command.kill_on_drop(true);
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let mut child = command.spawn().map_err(|_| PagebrowseError::NoManager)?;
+
+    Ok(ManagerStreams {
+        transport: ManagerTransport::Streams {
+            writer: Box::new(child.stdin.take().expect("stdin is piped")),
+            reader: Box::new(child.stdout.take().expect("stdout is piped")),
+        },
+        process: Some(ManagerProcess {
+            stderr: child.stderr.take().expect("stderr is piped"),
+            child,
+        }),
+    })
+}
+
+/// Starts the tasks that write requests to and read responses from a manager.
+/// The manager's input closes once the returned connection is dropped.
+fn start_connection(
+    streams: ManagerStreams,
+    generation: u32,
+    browser: Weak<PagebrowserInner>,
+) -> ManagerConnection {
+    let ManagerStreams { process, transport } = streams;
+
+    let (tx_request, rx_request) = mpsc::unbounded_channel();
+    let pending = Arc::new(PendingResponses::new());
+    let events = Arc::new(EventSubscribers::new());
+
+    let output = match transport {
+        ManagerTransport::Streams { writer, reader } => {
+            // Switched by the reader once the manager has agreed to the handshake's framing.
+            // Nothing else is sent until then, in case the manager predates the handshake.
+            let (tx_framing, rx_framing) = watch::channel(Framing::Base64);
+            tokio::spawn(write_requests(
+                writer,
+                rx_request,
+                rx_framing,
+                pending.clone(),
+            ));
+            ManagerOutput::Stream { reader, tx_framing }
         }
+        ManagerTransport::InProcess(connection) => {
+            tokio::spawn(forward_requests(
+                connection.requests,
+                rx_request,
+                pending.clone(),
+            ));
+            ManagerOutput::InProcess(connection.responses)
+        }
+    };
+    watch_manager(
+        browser,
+        generation,
+        process,
+        output,
+        pending.clone(),
+        events.clone(),
+    );
+
+    ManagerConnection {
+        generation,
+        tx_request,
+        pending,
+        events,
     }
+}
 
-    pub async fn evaluate_script(
-        &self,
-        script: String,
-    ) -> Result<Option<serde_json::Value>, PagebrowseError> {
-        let response = self
-            .browser
-            .send_command(PBRequestPayload::EvaluateScript {
-                window_id: self.id,
-                script,
-            })
-            .await?;
+/// Writes queued requests to the manager, flushing once the queue is empty
+async fn write_requests(
+    mut writer: ManagerWriter,
+    mut rx_request: mpsc::UnboundedReceiver<PBRequest>,
+    rx_framing: watch::Receiver<Framing>,
+    pending: Arc<PendingResponses>,
+) {
+    while let Some(request) = rx_request.recv().await {
+        let mut batch = vec![request];
+        while let Ok(request) = rx_request.try_recv() {
+            batch.push(request);
+        }
+        let framing = *rx_framing.borrow();
 
-        match response {
-            PBResponsePayload::ScriptEvaluated { output } => {
-                if output.is_empty() {
-                    return Ok(None);
+        let mut buf = vec![];
+        let mut written_ids = vec![];
+        for request in batch {
+            let message_id = request
+                .message_id
+                .expect("Outbound requests have a message ID");
+            match serde_json::to_vec(&request) {
+                Ok(json) => {
+                    let frame = match framing {
+                        Framing::Base64 => framing::encode_base64(&json),
+                        Framing::LengthPrefixed => framing::encode_length_prefixed(&json, &[]),
+                    };
+                    buf.extend_from_slice(&frame);
+                    written_ids.push(message_id);
                 }
+                Err(e) => {
+                    pending.resolve(message_id, Err(PagebrowseError::Protocol(e.to_string())))
+                }
+            }
+        }
+
+        let written = async {
+            writer.write_all(&buf).await?;
+            writer.flush().await
+        };
+        if let Err(e) = written.await {
+            for message_id in written_ids {
+                let error = std::io::Error::new(e.kind(), e.to_string());
+                pending.resolve(message_id, Err(error.into()));
+            }
+        }
+    }
+}
+
+/// Hands queued requests to a manager in this process
+async fn forward_requests(
+    requests: std::sync::mpsc::Sender<PBRequest>,
+    mut rx_request: mpsc::UnboundedReceiver<PBRequest>,
+    pending: Arc<PendingResponses>,
+) {
+    while let Some(request) = rx_request.recv().await {
+        let message_id = request
+            .message_id
+            .expect("Outbound requests have a message ID");
+
+        if requests.send(request).is_err() {
+            pending.resolve(
+                message_id,
+                Err(PagebrowseError::Protocol(
+                    "In-process manager has stopped".into(),
+                )),
+            );
+        }
+    }
+}
+
+/// Where responses from the manager arrive
+enum ManagerOutput {
+    Stream {
+        reader: ManagerReader,
+        tx_framing: watch::Sender<Framing>,
+    },
+    InProcess(std::sync::mpsc::Receiver<PBResponse>),
+}
+
+/// Reads responses from the manager until it exits or disconnects, then fails any pending
+/// requests and gives the browser a chance to restart the manager.
+fn watch_manager(
+    browser: Weak<PagebrowserInner>,
+    generation: u32,
+    process: Option<ManagerProcess>,
+    output: ManagerOutput,
+    pending: Arc<PendingResponses>,
+    events: Arc<EventSubscribers>,
+) {
+    tokio::spawn(async move {
+        let (child, stderr_task) = match process {
+            Some(ManagerProcess { child, stderr }) => {
+                (Some(child), Some(tokio::spawn(capture_stderr(stderr))))
+            }
+            None => (None, None),
+        };
 
-                serde_json::from_str::<serde_json::Value>(&output)
-                    .map(|v| Some(v))
-                    .map_err(|_e| PagebrowseError::Unknown)
+        let read = match output {
+            ManagerOutput::Stream { reader, tx_framing } => {
+                read_responses(reader, &tx_framing, &pending, &events).await
             }
-            _ => Err(PagebrowseError::Unknown),
+            ManagerOutput::InProcess(responses) => {
+                receive_responses(responses, pending.clone(), events.clone()).await;
+                Ok(())
+            }
+        };
+        let graceful = read.is_ok();
+        if let Err(message) = read {
+            pending.close(ConnectionClosed::Garbled(message));
+        }
+
+        // We only know how a manager exited if we spawned it
+        let status = match child {
+            Some(child) => wait_for_exit(child, graceful).await,
+            None => None,
+        };
+
+        let stderr = match stderr_task {
+            Some(stderr_task) => match timeout(Duration::from_secs(1), stderr_task).await {
+                Ok(Ok(stderr)) => stderr,
+                _ => String::new(),
+            },
+            None => String::new(),
+        };
+
+        let exit = ManagerExit { status, stderr };
+        pending.close(ConnectionClosed::Exited(exit.clone()));
+        events.close();
+
+        if let Some(inner) = browser.upgrade() {
+            Pagebrowser { inner }.handle_exit(generation, exit).await;
+        }
+    });
+}
+
+/// Waits for a manager process to exit after closing its output,
+/// killing it if it takes too long or misbehaved
+async fn wait_for_exit(mut child: Child, graceful: bool) -> Option<ExitStatus> {
+    if graceful {
+        if let Ok(status) = timeout(EXIT_GRACE_PERIOD, child.wait()).await {
+            return status.ok();
         }
     }
 
-    pub async fn resize_window(&self, width: usize, height: usize) -> Result<(), PagebrowseError> {
-        let response = self
-            .browser
-            .send_command(PBRequestPayload::ResizeWindow {
-                window_id: self.id,
-                width,
-                height,
-            })
-            .await?;
+    _ = child.start_kill();
+    child.wait().await.ok()
+}
 
-        match response {
-            PBResponsePayload::OperationComplete => Ok(()),
-            _ => Err(PagebrowseError::Unknown),
+/// Routes responses from the manager to their requests until the manager closes its output,
+/// or errors if the manager sends something we can't parse.
+async fn read_responses(
+    reader: ManagerReader,
+    tx_framing: &watch::Sender<Framing>,
+    pending: &PendingResponses,
+    events: &EventSubscribers,
+) -> Result<(), String> {
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let framing = *tx_framing.borrow();
+        let frame = match framing {
+            Framing::Base64 => read_base64_frame(&mut reader).await?,
+            Framing::LengthPrefixed => read_length_prefixed_frame(&mut reader).await?,
+        };
+        let Some((json, attachments)) = frame else {
+            // EOF Reached
+            return Ok(());
+        };
+
+        let mut response = serde_json::from_slice::<PBResponse>(&json)
+            .map_err(|e| format!("Received garbled json from the manager: {e}"))?;
+        response
+            .restore_attachments(attachments)
+            .map_err(|e| format!("Received a garbled frame from the manager: {e}"))?;
+
+        // Everything after the handshake response is in the framing it agreed to
+        if let PBResponsePayload::Initialized { framing, .. } = &response.payload {
+            tx_framing.send_replace(*framing);
         }
+
+        route_response(response, pending, events);
     }
+}
 
-    pub async fn screenshot(&self, path: String) -> Result<(), PagebrowseError> {
-        let response = self
-            .browser
-            .send_command(PBRequestPayload::Screenshot {
-                window_id: self.id,
-                path,
-            })
-            .await?;
+/// Routes responses from a manager in this process until it drops its end of the connection
+async fn receive_responses(
+    responses: std::sync::mpsc::Receiver<PBResponse>,
+    pending: Arc<PendingResponses>,
+    events: Arc<EventSubscribers>,
+) {
+    let (tx_done, rx_done) = oneshot::channel();
 
-        match response {
-            PBResponsePayload::OperationComplete => Ok(()),
-            _ => Err(PagebrowseError::Unknown),
+    // Receiving blocks, and a spawn_blocking task would hold up the runtime shutting down
+    std::thread::spawn(move || {
+        for response in responses {
+            route_response(response, &pending, &events);
+        }
+        _ = tx_done.send(());
+    });
+
+    _ = rx_done.await;
+}
+
+fn route_response(response: PBResponse, pending: &PendingResponses, events: &EventSubscribers) {
+    // Responses without an ID are either events, or errors for messages the manager couldn't parse
+    let Some(message_id) = response.message_id else {
+        if let PBResponsePayload::PageEvent { window_id, event } = response.payload {
+            events.publish(window_id, event);
         }
+        return;
+    };
+
+    let result = match response.payload {
+        PBResponsePayload::Error {
+            original_message,
+            message,
+        } => Err(PagebrowseError::ManagerError {
+            message,
+            original_message,
+        }),
+        payload => Ok(payload),
+    };
+
+    pending.resolve(message_id, result);
+}
+
+/// Returns the JSON of the next frame, or `None` once the manager has closed its output
+async fn read_base64_frame(
+    reader: &mut (impl AsyncBufRead + Unpin),
+) -> Result<Option<(Vec<u8>, Vec<Vec<u8>>)>, String> {
+    let mut buf = vec![];
+    reader
+        .read_until(b',', &mut buf)
+        .await
+        .map_err(|e| format!("Failed to read from the manager: {e}"))?;
+
+    if buf.pop() != Some(b',') {
+        return Ok(None);
+    }
+
+    let Ok(decoded) = general_purpose::STANDARD.decode(&buf) else {
+        let msg = String::from_utf8_lossy(&buf);
+        return Err(format!("Received garbled base64 from the manager: {msg}"));
+    };
+
+    Ok(Some((decoded, vec![])))
+}
+
+/// Returns the JSON and attachments of the next frame, or `None` once the manager has closed its output
+async fn read_length_prefixed_frame(
+    reader: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<(Vec<u8>, Vec<Vec<u8>>)>, String> {
+    let mut header = [0; framing::HEADER_LEN];
+    // Only running out before the first byte is a clean end of the output
+    match reader.read(&mut header[..1]).await {
+        Ok(0) => return Ok(None),
+        Ok(_) => {}
+        Err(e) => return Err(format!("Failed to read from the manager: {e}")),
+    }
+    reader
+        .read_exact(&mut header[1..])
+        .await
+        .map_err(|e| format!("Failed to read from the manager: {e}"))?;
+
+    let garbled = |e| format!("Received a garbled frame from the manager: {e}");
+    let (json_len, attachment_count) = framing::parse_header(header).map_err(garbled)?;
+
+    let mut json = vec![0; json_len];
+    reader
+        .read_exact(&mut json)
+        .await
+        .map_err(|e| format!("Failed to read from the manager: {e}"))?;
+
+    let mut attachments = Vec::with_capacity(attachment_count);
+    for _ in 0..attachment_count {
+        let mut prefix = [0; 4];
+        reader
+            .read_exact(&mut prefix)
+            .await
+            .map_err(|e| format!("Failed to read from the manager: {e}"))?;
+
+        let mut attachment = vec![0; framing::parse_length(prefix).map_err(garbled)?];
+        reader
+            .read_exact(&mut attachment)
+            .await
+            .map_err(|e| format!("Failed to read from the manager: {e}"))?;
+        attachments.push(attachment);
+    }
+
+    Ok(Some((json, attachments)))
+}
+
+/// Reads the manager's stderr, keeping the tail of it to report if the manager exits
+async fn capture_stderr(stderr: ChildStderr) -> String {
+    let mut reader = BufReader::new(stderr);
+    let mut captured = String::new();
+
+    loop {
+        let mut buf = vec![];
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+
+        captured.push_str(&String::from_utf8_lossy(&buf));
+
+        if captured.len() > STDERR_CAPTURE_LIMIT {
+            let excess = captured.len() - STDERR_CAPTURE_LIMIT;
+            let cut = (excess..captured.len())
+                .find(|i| captured.is_char_boundary(*i))
+                .unwrap_or(captured.len());
+            captured.drain(..cut);
+        }
+    }
+
+    captured
+}
+
+struct ManagerState {
+    connection: ManagerConnection,
+    /// Given to the most recently started manager, which may have failed to start
+    latest_generation: u32,
+    running: bool,
+    last_exit: Option<ManagerExit>,
+    /// Why the manager couldn't be restarted after it last exited, if it couldn't
+    restart_error: Option<String>,
+    restarts: u32,
+    source: ManagerSource,
+    params: InitializationParams,
+    /// What the current manager reported about itself when it was initialized
+    manager: Option<ManagerInfo>,
+    restart_policy: RestartPolicy,
+}
+
+impl ManagerState {
+    fn exit_error(&self) -> PagebrowseError {
+        let exit = self.last_exit.clone().unwrap_or(ManagerExit {
+            status: None,
+            stderr: String::new(),
+        });
+
+        match &self.restart_error {
+            Some(error) => PagebrowseError::RestartFailed {
+                status: exit.status,
+                stderr: exit.stderr,
+                error: error.clone(),
+            },
+            None => exit.into(),
+        }
+    }
+
+    fn should_restart(&self) -> bool {
+        match self.restart_policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::Limited(max_restarts) => self.restarts < max_restarts,
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+struct PagebrowserInner {
+    latest_message_id: AtomicU32,
+    default_timeout: Option<Duration>,
+    startup_timeout: Duration,
+    /// Only held briefly to send, except while the manager is restarting
+    state: Mutex<ManagerState>,
+}
+
+impl PagebrowserInner {
+    fn next_message_id(&self) -> u32 {
+        self.latest_message_id.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+/// Cancels a command with the manager if its caller stops waiting for the response
+struct CancelOnDrop<'a> {
+    browser: &'a PagebrowserInner,
+    tx_request: mpsc::UnboundedSender<PBRequest>,
+    pending: Arc<PendingResponses>,
+    message_id: u32,
+    armed: bool,
+}
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        if !self.armed || !self.pending.forget(self.message_id) {
+            return;
+        }
+
+        let cancel_id = self.browser.next_message_id();
+        // Nothing waits on the response, so it is dropped when it arrives
+        _ = self.tx_request.send(PBRequest {
+            message_id: Some(cancel_id),
+            payload: PBRequestPayload::Cancel {
+                message_id: self.message_id,
+            },
+        });
+    }
+}
+
+#[derive(Clone)]
+pub struct Pagebrowser {
+    inner: Arc<PagebrowserInner>,
+}
+
+impl Pagebrowser {
+    /// Sends a command, failing if the manager has restarted since `generation`,
+    /// and cancelling it with the manager if no response arrives within `timeout`
+    async fn send_command_to(
+        &self,
+        generation: Option<u32>,
+        command: PBRequestPayload,
+        timeout: Option<Duration>,
+    ) -> Result<PBResponsePayload, PagebrowseError> {
+        let (rx, mut guard) = {
+            let state = self.inner.state.lock().await;
+
+            if !state.running || generation.is_some_and(|g| g != state.connection.generation) {
+                return Err(state.exit_error());
+            }
+
+            let message_id = self.inner.next_message_id();
+            let rx = state.connection.send(PBRequest {
+                message_id: Some(message_id),
+                payload: command,
+            })?;
+
+            let guard = CancelOnDrop {
+                browser: &self.inner,
+                tx_request: state.connection.tx_request.clone(),
+                pending: state.connection.pending.clone(),
+                message_id,
+                armed: true,
+            };
+
+            (rx, guard)
+        };
+
+        let response = match timeout {
+            Some(duration) => tokio::time::timeout(duration, receive_response(rx))
+                .await
+                .ok(),
+            None => Some(receive_response(rx).await),
+        };
+
+        match response {
+            Some(response) => {
+                guard.armed = false;
+                response
+            }
+            // Dropping the guard cancels the command
+            None => Err(PagebrowseError::Timeout),
+        }
+    }
+
+    async fn is_connected_to_socket(&self) -> bool {
+        let state = self.inner.state.lock().await;
+        matches!(state.source, ManagerSource::Connect(_))
+    }
+
+    async fn current_generation(&self) -> u32 {
+        self.inner.state.lock().await.connection.generation
+    }
+
+    async fn handle_exit(&self, generation: u32, exit: ManagerExit) {
+        let mut state = self.inner.state.lock().await;
+
+        if state.connection.generation != generation {
+            // A manager that failed to start during a restart
+            return;
+        }
+
+        state.running = false;
+        state.last_exit = Some(exit);
+        state.restart_error = None;
+
+        let mut backoff = RESTART_BACKOFF;
+        let mut attempts = 0;
+        while state.should_restart() && attempts < MAX_RESTART_ATTEMPTS {
+            if attempts > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+            attempts += 1;
+            state.restarts += 1;
+
+            // Commands report the failure once there are no attempts left
+            match self.restart(&mut state).await {
+                Ok(()) => {
+                    state.restart_error = None;
+                    return;
+                }
+                Err(e) => state.restart_error = Some(e.to_string()),
+            }
+        }
+    }
+
+    /// Spawns (or reconnects to) and initializes a new manager while the state is locked,
+    /// so that commands wait for the restart rather than failing.
+    /// Gives up on a manager that doesn't finish starting within the startup timeout.
+    async fn restart(&self, state: &mut ManagerState) -> Result<(), PagebrowseError> {
+        // Every attempt gets its own generation, so that one that fails to start
+        // can't be mistaken for the manager that replaces it
+        state.latest_generation += 1;
+        let generation = state.latest_generation;
+
+        let started = timeout(self.inner.startup_timeout, async {
+            let streams = open_manager(&state.source).await?;
+            let connection = start_connection(streams, generation, Arc::downgrade(&self.inner));
+
+            let rx = connection.send(PBRequest {
+                message_id: Some(self.inner.next_message_id()),
+                payload: PBRequestPayload::Initialize(state.params.clone()),
+            })?;
+            let manager = check_handshake(receive_response(rx).await?)?;
+
+            Ok::<_, PagebrowseError>((connection, manager))
+        });
+        // Dropping an unanswered connection closes the replacement manager's input
+        let (connection, manager) = started.await.map_err(|_| PagebrowseError::Timeout)??;
+
+        state.connection = connection;
+        state.manager = Some(manager);
+        state.running = true;
+
+        Ok(())
+    }
+}
+
+impl Pagebrowser {
+    /// The version, platform and capabilities of the manager currently serving this browser
+    pub async fn manager_info(&self) -> ManagerInfo {
+        self.inner
+            .state
+            .lock()
+            .await
+            .manager
+            .clone()
+            .expect("Manager is initialized before the browser is returned")
+    }
+
+    pub async fn get_window(&self) -> Result<PagebrowserWindow, PagebrowseError> {
+        let generation = self.current_generation().await;
+        let window_response = self
+            .send_command_to(
+                Some(generation),
+                PBRequestPayload::NewWindow,
+                self.inner.default_timeout,
+            )
+            .await?;
+
+        let PBResponsePayload::NewWindowCreated { id } = window_response else {
+            return Err(PagebrowseError::UnexpectedResponse(window_response));
+        };
+
+        Ok(PagebrowserWindow {
+            handle: Arc::new(WindowHandle {
+                id,
+                generation,
+                browser: self.clone(),
+            }),
+            timeout: self.inner.default_timeout,
+        })
+    }
+}
+
+/// Releases the window once every `PagebrowserWindow` referring to it is dropped
+struct WindowHandle {
+    id: u32,
+    generation: u32,
+    browser: Pagebrowser,
+}
+
+impl Drop for WindowHandle {
+    fn drop(&mut self) {
+        let window_id = self.id;
+        let generation = self.generation;
+        let browser_ref = self.browser.clone();
+
+        tokio::spawn(async move {
+            // Nothing is waiting on a drop to report a failure to, and a window that can't be
+            // released has either gone with its manager or is already being released
+            _ = browser_ref
+                .send_command_to(
+                    Some(generation),
+                    PBRequestPayload::ReleaseWindow { window_id },
+                    browser_ref.inner.default_timeout,
+                )
+                .await;
+
+            let state = browser_ref.inner.state.lock().await;
+            if state.connection.generation == generation {
+                state.connection.events.remove(window_id);
+            }
+        });
+    }
+}
+
+/// Events from a window's pages, from `PagebrowserWindow::events`
+pub struct PageEvents {
+    rx: mpsc::UnboundedReceiver<PageEvent>,
+}
+
+impl PageEvents {
+    /// Waits for the next event, or returns `None` once the stream has ended
+    pub async fn recv(&mut self) -> Option<PageEvent> {
+        self.rx.recv().await
+    }
+}
+
+impl Stream for PageEvents {
+    type Item = PageEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Console messages logged in a window, from `PagebrowserWindow::console_messages`
+pub struct ConsoleMessages {
+    events: PageEvents,
+}
+
+impl ConsoleMessages {
+    /// Waits for the next message, or returns `None` once the stream has ended
+    pub async fn recv(&mut self) -> Option<ConsoleMessage> {
+        while let Some(event) = self.events.recv().await {
+            if let PageEvent::Console(message) = event {
+                return Some(message);
+            }
+        }
+        None
+    }
+}
+
+impl Stream for ConsoleMessages {
+    type Item = ConsoleMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match self.events.rx.poll_recv(cx) {
+                Poll::Ready(Some(PageEvent::Console(message))) => {
+                    return Poll::Ready(Some(message))
+                }
+                Poll::Ready(Some(_)) => continue,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PagebrowserWindow {
+    handle: Arc<WindowHandle>,
+    timeout: Option<Duration>,
+}
+
+impl PagebrowserWindow {
+    /// Returns a handle to the same window that uses a different timeout for its commands.
+    /// `None` waits indefinitely.
+    pub fn with_timeout(&self, timeout: Option<Duration>) -> PagebrowserWindow {
+        PagebrowserWindow {
+            handle: self.handle.clone(),
+            timeout,
+        }
+    }
+
+    async fn send(&self, command: PBRequestPayload) -> Result<PBResponsePayload, PagebrowseError> {
+        self.handle
+            .browser
+            .send_command_to(Some(self.handle.generation), command, self.timeout)
+            .await
+    }
+
+    /// Navigates to `url`, resolving once the `wait_until` condition holds.
+    /// With no condition, resolves as soon as the navigation has started.
+    pub async fn navigate(
+        &self,
+        url: String,
+        wait_until: Option<WaitUntil>,
+    ) -> Result<NavigationResult, PagebrowseError> {
+        let response = self
+            .send(PBRequestPayload::Navigate {
+                window_id: self.handle.id,
+                url,
+                wait_until,
+            })
+            .await?;
+
+        match response {
+            PBResponsePayload::NavigationComplete(result) => Ok(result),
+            other => Err(PagebrowseError::UnexpectedResponse(other)),
+        }
+    }
+
+    pub async fn evaluate_script(
+        &self,
+        script: String,
+    ) -> Result<Option<serde_json::Value>, PagebrowseError> {
+        let output = self.run_script(script, serde_json::Map::new()).await?;
+
+        if output.is_empty() {
+            return Ok(None);
+        }
+
+        serde_json::from_str::<serde_json::Value>(&output)
+            .map(Some)
+            .map_err(PagebrowseError::InvalidOutput)
+    }
+
+    /// Runs `script` as the body of an async function, and deserializes its return value.
+    /// `args` must serialize to a map, and each entry is available to the script as a variable.
+    pub async fn evaluate<Args: Serialize, R: DeserializeOwned>(
+        &self,
+        script: String,
+        args: Args,
+    ) -> Result<R, PagebrowseError> {
+        let arguments = match serde_json::to_value(args) {
+            Ok(serde_json::Value::Object(map)) => map,
+            Ok(serde_json::Value::Null) => serde_json::Map::new(),
+            Ok(_) => {
+                return Err(PagebrowseError::InvalidArguments(
+                    "arguments must serialize to a map of names to values".into(),
+                ))
+            }
+            Err(e) => return Err(PagebrowseError::InvalidArguments(e.to_string())),
+        };
+
+        let output = self.run_script(script, arguments).await?;
+
+        // Scripts that return undefined produce no output
+        if output.is_empty() {
+            return serde_json::from_value(serde_json::Value::Null)
+                .map_err(PagebrowseError::InvalidOutput);
+        }
+
+        serde_json::from_str(&output).map_err(PagebrowseError::InvalidOutput)
+    }
+
+    async fn run_script(
+        &self,
+        script: String,
+        arguments: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<String, PagebrowseError> {
+        let response = self
+            .send(PBRequestPayload::EvaluateScript {
+                window_id: self.handle.id,
+                script,
+                arguments,
+            })
+            .await?;
+
+        match response {
+            PBResponsePayload::ScriptEvaluated { output } => Ok(output),
+            PBResponsePayload::ScriptError {
+                message,
+                line,
+                column,
+                stack,
+            } => Err(PagebrowseError::ScriptError {
+                message,
+                line,
+                column,
+                stack,
+            }),
+            other => Err(PagebrowseError::UnexpectedResponse(other)),
+        }
+    }
+
+    pub async fn resize_window(&self, width: usize, height: usize) -> Result<(), PagebrowseError> {
+        let response = self
+            .send(PBRequestPayload::ResizeWindow {
+                window_id: self.handle.id,
+                width,
+                height,
+            })
+            .await?;
+
+        match response {
+            PBResponsePayload::OperationComplete => Ok(()),
+            other => Err(PagebrowseError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Returns the cookies that would be sent with a request to `url`
+    pub async fn get_cookies(&self, url: String) -> Result<Vec<Cookie>, PagebrowseError> {
+        let response = self
+            .send(PBRequestPayload::GetCookies {
+                window_id: self.handle.id,
+                url: Some(url),
+            })
+            .await?;
+
+        match response {
+            PBResponsePayload::Cookies { cookies } => Ok(cookies),
+            other => Err(PagebrowseError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Adds cookies to the window's session, e.g. to log in before navigating
+    pub async fn set_cookies(&self, cookies: Vec<Cookie>) -> Result<(), PagebrowseError> {
+        let response = self
+            .send(PBRequestPayload::SetCookies {
+                window_id: self.handle.id,
+                cookies,
+            })
+            .await?;
+
+        match response {
+            PBResponsePayload::OperationComplete => Ok(()),
+            other => Err(PagebrowseError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Deletes every cookie in the window's session.
+    /// Unless sessions are isolated, this is shared by every window in the pool.
+    pub async fn clear_cookies(&self) -> Result<(), PagebrowseError> {
+        let response = self
+            .send(PBRequestPayload::ClearCookies {
+                window_id: self.handle.id,
+            })
+            .await?;
+
+        match response {
+            PBResponsePayload::OperationComplete => Ok(()),
+            other => Err(PagebrowseError::UnexpectedResponse(other)),
+        }
+    }
+
+    /// Streams everything that happens in this window's pages from now on.
+    /// The stream ends once the window is dropped, or the manager exits.
+    pub async fn events(&self) -> PageEvents {
+        let state = self.handle.browser.inner.state.lock().await;
+
+        let rx = if state.connection.generation == self.handle.generation {
+            state.connection.events.subscribe(self.handle.id)
+        } else {
+            // The window went away with the manager, so there's nothing to stream
+            mpsc::unbounded_channel().1
+        };
+
+        PageEvents { rx }
+    }
+
+    /// Streams the messages that pages in this window log to the console from now on.
+    /// The stream ends once the window is dropped, or the manager exits.
+    pub async fn console_messages(&self) -> ConsoleMessages {
+        ConsoleMessages {
+            events: self.events().await,
+        }
+    }
+
+    /// Captures all of the window's cookies, and the localStorage of the page it is on.
+    /// Only the current page's origin is captured, as other origins' localStorage can't be read
+    /// without navigating to them, so visit each origin you need and merge the results.
+    pub async fn storage_state(&self) -> Result<StorageState, PagebrowseError> {
+        let response = self
+            .send(PBRequestPayload::GetCookies {
+                window_id: self.handle.id,
+                url: None,
+            })
+            .await?;
+
+        let cookies = match response {
+            PBResponsePayload::Cookies { cookies } => cookies,
+            other => return Err(PagebrowseError::UnexpectedResponse(other)),
+        };
+
+        let origin: Option<OriginStorage> = self
+            .evaluate(CAPTURE_LOCAL_STORAGE_SCRIPT.into(), ())
+            .await?;
+
+        Ok(StorageState {
+            cookies,
+            origins: origin.into_iter().collect(),
+        })
+    }
+
+    /// Writes the window's `storage_state` to a JSON file,
+    /// to be loaded with `PagebrowseBuilder::storage_state`.
+    /// Like `storage_state`, only the current page's localStorage is saved.
+    pub async fn save_storage_state(&self, path: impl AsRef<Path>) -> Result<(), PagebrowseError> {
+        let storage_state = self.storage_state().await?;
+        let json = serde_json::to_vec_pretty(&storage_state)
+            .map_err(PagebrowseError::InvalidStorageState)?;
+
+        tokio::fs::write(path, json).await?;
+        Ok(())
+    }
+
+    pub async fn screenshot(&self, path: String) -> Result<(), PagebrowseError> {
+        self.screenshot_with_options(path, ScreenshotOptions::default())
+            .await
+    }
+
+    /// Saves a screenshot to `path`. A manager listening on a socket won't write files
+    /// for its consumers, so with `PagebrowseBuilder::connect` it's written from here instead.
+    pub async fn screenshot_with_options(
+        &self,
+        path: String,
+        options: ScreenshotOptions,
+    ) -> Result<(), PagebrowseError> {
+        if self.handle.browser.is_connected_to_socket().await {
+            let bytes = self.screenshot_bytes_with_options(options).await?;
+            tokio::fs::write(path, bytes).await?;
+            return Ok(());
+        }
+
+        let response = self
+            .send(PBRequestPayload::Screenshot {
+                window_id: self.handle.id,
+                path: Some(path),
+                options,
+            })
+            .await?;
+
+        match response {
+            PBResponsePayload::OperationComplete => Ok(()),
+            other => Err(PagebrowseError::UnexpectedResponse(other)),
+        }
+    }
+
+    pub async fn screenshot_bytes(&self) -> Result<Vec<u8>, PagebrowseError> {
+        self.screenshot_bytes_with_options(ScreenshotOptions::default())
+            .await
+    }
+
+    pub async fn screenshot_bytes_with_options(
+        &self,
+        options: ScreenshotOptions,
+    ) -> Result<Vec<u8>, PagebrowseError> {
+        let response = self
+            .send(PBRequestPayload::Screenshot {
+                window_id: self.handle.id,
+                path: None,
+                options,
+            })
+            .await?;
+
+        match response {
+            PBResponsePayload::ScreenshotCaptured { bytes } => Ok(bytes),
+            other => Err(PagebrowseError::UnexpectedResponse(other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manager_info() -> ManagerInfo {
+        ManagerInfo {
+            version: "0.0.0".into(),
+            protocol_version: PROTOCOL_VERSION,
+            platform: "test".into(),
+            capabilities: vec![],
+        }
+    }
+
+    fn initialization_params() -> InitializationParams {
+        InitializationParams {
+            pool_size: 1,
+            visible: false,
+            init_script: None,
+            window_reset: WindowReset::default(),
+            isolated_sessions: false,
+            storage_state: None,
+            framing: Framing::Base64,
+            protocol_version: Some(PROTOCOL_VERSION),
+        }
+    }
+
+    /// Answers requests over a duplex stream with base64 frames, reporting each one it reads.
+    /// `NewWindow` is never answered, to leave something pending.
+    async fn fake_manager(stream: tokio::io::DuplexStream, seen: mpsc::UnboundedSender<PBRequest>) {
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut reader = BufReader::new(reader);
+
+        while let Ok(Some((json, _))) = read_base64_frame(&mut reader).await {
+            let request: PBRequest = serde_json::from_slice(&json).unwrap();
+            let payload = match &request.payload {
+                PBRequestPayload::NewWindow => None,
+                PBRequestPayload::Tester(message) => {
+                    Some(PBResponsePayload::Tester(message.clone()))
+                }
+                _ => Some(PBResponsePayload::OperationComplete),
+            };
+            let message_id = request.message_id;
+            _ = seen.send(request);
+
+            if let Some(payload) = payload {
+                let json = serde_json::to_vec(&PBResponse {
+                    message_id,
+                    payload,
+                })
+                .unwrap();
+                writer
+                    .write_all(&framing::encode_base64(&json))
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    /// A browser talking to `fake_manager`, without a handshake
+    fn duplex_browser() -> (Pagebrowser, mpsc::UnboundedReceiver<PBRequest>) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let (seen_tx, seen_rx) = mpsc::unbounded_channel();
+        tokio::spawn(fake_manager(server, seen_tx));
+
+        let (reader, writer) = tokio::io::split(client);
+        let streams = ManagerStreams {
+            process: None,
+            transport: ManagerTransport::Streams {
+                writer: Box::new(writer),
+                reader: Box::new(reader),
+            },
+        };
+
+        let browser = Pagebrowser {
+            inner: Arc::new_cyclic(|browser| PagebrowserInner {
+                latest_message_id: AtomicU32::new(0),
+                default_timeout: None,
+                startup_timeout: STARTUP_TIMEOUT,
+                state: Mutex::new(ManagerState {
+                    connection: start_connection(streams, 0, browser.clone()),
+                    latest_generation: 0,
+                    running: true,
+                    last_exit: None,
+                    restart_error: None,
+                    restarts: 0,
+                    source: ManagerSource::Spawn("pagebrowse_manager".into()),
+                    params: initialization_params(),
+                    manager: None,
+                    restart_policy: RestartPolicy::Never,
+                }),
+            }),
+        };
+
+        (browser, seen_rx)
+    }
+
+    /// Connects to a manager on its own thread, which exits when asked to with `Tester("exit")`
+    fn in_process_manager() -> InProcessConnection {
+        slow_in_process_manager(Duration::ZERO)
+    }
+
+    /// Like `in_process_manager`, but taking `startup` to answer `Initialize`
+    fn slow_in_process_manager(startup: Duration) -> InProcessConnection {
+        let (requests, rx_requests) = std::sync::mpsc::channel::<PBRequest>();
+        let (tx_responses, responses) = std::sync::mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut next_window = 0;
+            for request in rx_requests {
+                let payload = match request.payload {
+                    PBRequestPayload::Initialize(params) => {
+                        std::thread::sleep(startup);
+                        PBResponsePayload::Initialized {
+                            framing: params.framing,
+                            manager: manager_info(),
+                        }
+                    }
+                    PBRequestPayload::NewWindow => {
+                        next_window += 1;
+                        PBResponsePayload::NewWindowCreated { id: next_window }
+                    }
+                    PBRequestPayload::Tester(message) if message == "exit" => break,
+                    PBRequestPayload::Tester(message) => PBResponsePayload::Tester(message),
+                    _ => PBResponsePayload::OperationComplete,
+                };
+
+                _ = tx_responses.send(PBResponse {
+                    message_id: request.message_id,
+                    payload,
+                });
+            }
+        });
+
+        InProcessConnection {
+            requests,
+            responses,
+        }
+    }
+
+    /// Connects to a manager that has already exited
+    fn exited_in_process_manager() -> InProcessConnection {
+        let (requests, _) = std::sync::mpsc::channel();
+        let (_, responses) = std::sync::mpsc::channel();
+        InProcessConnection {
+            requests,
+            responses,
+        }
+    }
+
+    /// A connector that hands out `exited_in_process_manager` for the starts in `failing`
+    fn failing_starts(failing: std::ops::Range<u32>) -> impl Fn() -> InProcessConnection {
+        let starts = AtomicU32::new(0);
+        move || {
+            if failing.contains(&starts.fetch_add(1, Ordering::Relaxed)) {
+                exited_in_process_manager()
+            } else {
+                in_process_manager()
+            }
+        }
+    }
+
+    async fn exit_manager(browser: &Pagebrowser) {
+        let exited = browser
+            .send_command_to(None, PBRequestPayload::Tester("exit".into()), None)
+            .await;
+        assert!(matches!(exited, Err(PagebrowseError::ManagerExited { .. })));
+    }
+
+    async fn wait_for_generation(browser: &Pagebrowser, generation: u32) {
+        timeout(Duration::from_secs(5), async {
+            while browser.current_generation().await != generation {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Manager should restart");
+    }
+
+    fn tester_response(message: &str) -> ResponseResult {
+        Ok(PBResponsePayload::Tester(message.into()))
+    }
+
+    #[tokio::test]
+    async fn pending_responses_are_routed_by_message_id() {
+        let pending = PendingResponses::new();
+        let first = pending.register(1).unwrap();
+        let second = pending.register(2).unwrap();
+
+        pending.resolve(2, tester_response("second"));
+        pending.resolve(3, tester_response("unknown"));
+        pending.resolve(1, tester_response("first"));
+
+        assert!(matches!(
+            receive_response(first).await,
+            Ok(PBResponsePayload::Tester(message)) if message == "first"
+        ));
+        assert!(matches!(
+            receive_response(second).await,
+            Ok(PBResponsePayload::Tester(message)) if message == "second"
+        ));
+
+        let forgotten = pending.register(4).unwrap();
+        assert!(pending.forget(4));
+        assert!(!pending.forget(4));
+        assert!(forgotten.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn closing_pending_responses_fails_every_request() {
+        let pending = PendingResponses::new();
+        let first = pending.register(1).unwrap();
+        let second = pending.register(2).unwrap();
+
+        pending.close(ConnectionClosed::Garbled("bad frame".into()));
+
+        for rx in [first, second] {
+            assert!(matches!(
+                receive_response(rx).await,
+                Err(PagebrowseError::Protocol(message)) if message == "bad frame"
+            ));
+        }
+        assert!(matches!(
+            pending.register(3),
+            Err(PagebrowseError::Protocol(_))
+        ));
+        assert!(!pending.forget(1));
+    }
+
+    #[tokio::test]
+    async fn timed_out_commands_are_cancelled() {
+        let (browser, mut seen) = duplex_browser();
+
+        let result = browser
+            .send_command_to(
+                None,
+                PBRequestPayload::NewWindow,
+                Some(Duration::from_millis(50)),
+            )
+            .await;
+        assert!(matches!(result, Err(PagebrowseError::Timeout)));
+
+        let new_window = seen.recv().await.unwrap();
+        assert!(matches!(new_window.payload, PBRequestPayload::NewWindow));
+        let cancel = seen.recv().await.unwrap();
+        assert!(matches!(
+            cancel.payload,
+            PBRequestPayload::Cancel { message_id } if Some(message_id) == new_window.message_id
+        ));
+
+        // Answered commands aren't cancelled
+        for message in ["first", "second"] {
+            let response = browser
+                .send_command_to(None, PBRequestPayload::Tester(message.into()), None)
+                .await;
+            assert!(matches!(response, Ok(PBResponsePayload::Tester(m)) if m == message));
+            let request = seen.recv().await.unwrap();
+            assert!(matches!(request.payload, PBRequestPayload::Tester(m) if m == message));
+        }
+    }
+
+    #[tokio::test]
+    async fn restarts_start_a_new_generation() {
+        let browser = PagebrowseBuilder::new(1)
+            .in_process(in_process_manager)
+            .restart_policy(RestartPolicy::Limited(1))
+            .build()
+            .await
+            .unwrap();
+        let window = browser.get_window().await.unwrap();
+        assert_eq!(window.handle.generation, 0);
+
+        exit_manager(&browser).await;
+        wait_for_generation(&browser, 1).await;
+
+        // Windows from the old manager are gone, but the new one serves new windows
+        let stale = browser
+            .send_command_to(Some(0), PBRequestPayload::Tester("stale".into()), None)
+            .await;
+        assert!(matches!(stale, Err(PagebrowseError::ManagerExited { .. })));
+        let window = browser.get_window().await.unwrap();
+        assert_eq!(window.handle.generation, 1);
+
+        // The restart policy only allowed one
+        exit_manager(&browser).await;
+        assert!(browser.get_window().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn startup_isnt_limited_by_the_command_timeout() {
+        let browser = PagebrowseBuilder::new(1)
+            .in_process(|| slow_in_process_manager(Duration::from_millis(100)))
+            .restart_policy(RestartPolicy::Limited(1))
+            .default_timeout(Duration::from_millis(10))
+            .build()
+            .await
+            .unwrap();
+
+        exit_manager(&browser).await;
+        wait_for_generation(&browser, 1).await;
+    }
+
+    #[tokio::test]
+    async fn failed_restarts_are_retried() {
+        let browser = PagebrowseBuilder::new(1)
+            .in_process(failing_starts(1..3))
+            .restart_policy(RestartPolicy::Always)
+            .build()
+            .await
+            .unwrap();
+
+        // The two managers that failed to start took the generations before it
+        exit_manager(&browser).await;
+        wait_for_generation(&browser, 3).await;
+        assert!(browser.get_window().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn restart_failures_are_reported() {
+        let browser = PagebrowseBuilder::new(1)
+            .in_process(failing_starts(1..u32::MAX))
+            .restart_policy(RestartPolicy::Limited(2))
+            .build()
+            .await
+            .unwrap();
+        exit_manager(&browser).await;
+
+        // Commands that arrive before the restarts have been given up on see the exit itself
+        let failed = timeout(Duration::from_secs(5), async {
+            loop {
+                match browser.get_window().await {
+                    Err(PagebrowseError::RestartFailed { error, .. }) => return error,
+                    Err(PagebrowseError::ManagerExited { .. }) => {
+                        tokio::time::sleep(Duration::from_millis(10)).await
+                    }
+                    other => panic!("Expected the restart to fail, got {:?}", other.err()),
+                }
+            }
+        })
+        .await
+        .expect("Restarts should be given up on");
+        assert!(!failed.is_empty());
+    }
+
+    #[test]
+    fn manager_addresses_are_parsed() {
+        assert!(matches!(
+            "unix:/tmp/pagebrowse.sock".parse(),
+            Ok(ManagerAddr::Unix(path)) if path == Path::new("/tmp/pagebrowse.sock")
+        ));
+        assert!(matches!(
+            "tcp:127.0.0.1:9000".parse(),
+            Ok(ManagerAddr::Tcp(host)) if host == "127.0.0.1:9000"
+        ));
+
+        for addr in ["unix:", "tcp:", "127.0.0.1:9000", "http://localhost"] {
+            assert!(matches!(
+                addr.parse::<ManagerAddr>(),
+                Err(PagebrowseError::InvalidAddress(a)) if a == addr
+            ));
+        }
+    }
+
+    #[test]
+    fn handshakes_check_the_protocol_version() {
+        let initialized = |manager| PBResponsePayload::Initialized {
+            framing: Framing::LengthPrefixed,
+            manager,
+        };
+
+        assert_eq!(
+            check_handshake(initialized(manager_info())).unwrap(),
+            manager_info()
+        );
+
+        let newer = ManagerInfo {
+            protocol_version: PROTOCOL_VERSION + 1,
+            ..manager_info()
+        };
+        assert!(matches!(
+            check_handshake(initialized(newer)),
+            Err(PagebrowseError::IncompatibleManager {
+                protocol_version: Some(v),
+                ..
+            }) if v == PROTOCOL_VERSION + 1
+        ));
+
+        assert!(matches!(
+            check_handshake(PBResponsePayload::OperationComplete),
+            Err(PagebrowseError::IncompatibleManager {
+                version: None,
+                protocol_version: None,
+            })
+        ));
+        assert!(matches!(
+            check_handshake(PBResponsePayload::Tester("hi".into())),
+            Err(PagebrowseError::UnexpectedResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn frames_end_cleanly_at_eof() {
+        let json = br#"{"message_id":0,"payload":"OperationComplete"}"#;
+
+        let mut frame = framing::encode_length_prefixed(json, &[b"attachment".to_vec()]);
+        let mut reader = &frame[..];
+        let (read_json, attachments) = read_length_prefixed_frame(&mut reader)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(read_json, json);
+        assert_eq!(attachments, vec![b"attachment".to_vec()]);
+        assert_eq!(read_length_prefixed_frame(&mut reader).await, Ok(None));
+
+        // A frame that stops partway through is an error, rather than the end of the output
+        for len in [framing::HEADER_LEN + 4, 2] {
+            frame.truncate(len);
+            assert!(read_length_prefixed_frame(&mut &frame[..]).await.is_err());
+        }
+
+        let frame = framing::encode_base64(json);
+        let mut reader = &frame[..];
+        let (read_json, _) = read_base64_frame(&mut reader).await.unwrap().unwrap();
+        assert_eq!(read_json, json);
+        assert_eq!(read_base64_frame(&mut reader).await, Ok(None));
+        assert_eq!(read_base64_frame(&mut &b"eyJt"[..]).await, Ok(None));
     }
 }
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use futures::future::join_all;
 use pagebrowse_lib::*;
 use tokio::time::{sleep, Duration};
@@ -20,12 +22,12 @@ async fn main() -> Result<(), PagebrowseError> {
     // )
     // .await;
 
-    join_all(
-        windows
-            .iter()
-            .flatten()
-            .map(|window| window.navigate("https://pagefind.app/".into(), true)),
-    )
+    join_all(windows.iter().flatten().map(|window| {
+        window.navigate(
+            "https://pagefind.app/".into(),
+            Some(WaitUntil::Selector("h1".into())),
+        )
+    }))
     .await;
 
     join_all(
@@ -36,14 +38,14 @@ async fn main() -> Result<(), PagebrowseError> {
     )
     .await;
 
-    sleep(Duration::from_millis(3000)).await;
-
     let scripts = join_all(windows.iter().flatten().enumerate().map(|(i, window)| {
-        window.evaluate_script(format!(
+        window.evaluate::<_, String>(
             "let v = document.querySelector(`h1`).innerText;\n\
-             document.querySelector(`h1`).innerText = `Window {i}`;\n\
+             document.querySelector(`h1`).innerText = title;\n\
              return \"title was originally \" + v;"
-        ))
+                .into(),
+            HashMap::from([("title", format!("Window {i}"))]),
+        )
     }))
     .await;
 
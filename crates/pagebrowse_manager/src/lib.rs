@@ -1,11 +1,35 @@
 use serde::{Deserialize, Serialize};
 
 pub use pagebrowse_types::{
-    InitializationParams, PBRequest, PBRequestPayload, PBResponse, PBResponsePayload,
+    Capability, Cookie, InProcessConnection, InitializationParams, ManagerInfo, NavigationResult,
+    PBRequest, PBRequestPayload, PBResponse, PBResponsePayload, PageEvent, ScreenshotOptions,
+    StorageState, WaitUntil, WindowReset, PROTOCOL_VERSION,
 };
 
+mod lifecycle;
+mod manager;
 pub mod options;
 pub mod platforms;
+mod pool;
+mod screenshot;
+mod script;
+mod storage;
+mod transport;
+
+pub use manager::Manager;
+pub use transport::ListenAddr;
+
+/// What this manager tells consumers about itself when they initialize
+pub fn manager_info() -> ManagerInfo {
+    use platforms::PBPlatform;
+
+    ManagerInfo {
+        version: env!("CARGO_PKG_VERSION").into(),
+        protocol_version: PROTOCOL_VERSION,
+        platform: std::env::consts::OS.into(),
+        capabilities: platforms::Platform::capabilities(),
+    }
+}
 
 #[derive(Debug)]
 pub enum PBEvent {
@@ -15,8 +39,49 @@ pub enum PBEvent {
 
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub enum PBWebviewEvent {
-    PageLoadStart { url: String },
-    PageLoadFinish { url: String },
+    PageLoadStart {
+        url: String,
+    },
+    PageLoadFinish {
+        url: String,
+    },
+    /// The main resource of the page responded
+    ResponseReceived {
+        url: String,
+        status: Option<u16>,
+        mime_type: Option<String>,
+    },
+    PageLoadFailed {
+        url: String,
+        error: String,
+    },
+    /// Posted by the lifecycle script once the document has been parsed
+    DomContentLoaded {
+        url: String,
+    },
+    /// Something to pass on to the window's consumer
+    Page(PageEvent),
+    /// An in-page wait condition check resolved
+    WaitConditionMet {
+        check_id: u64,
+        url: String,
+    },
+    WaitConditionFailed {
+        check_id: u64,
+        error: String,
+    },
+    /// A released webview's blank page didn't finish loading in time
+    BlankTimedOut {
+        reset_id: u64,
+    },
+    /// A released webview has finished clearing its website data
+    ResetComplete {
+        error: Option<String>,
+    },
+    /// The initial storage state's cookies have been added to a webview
+    StorageStateLoaded {
+        error: Option<String>,
+    },
 }
 
 #[derive(Debug, PartialEq)]
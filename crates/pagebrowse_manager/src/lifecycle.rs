@@ -0,0 +1,283 @@
+use pagebrowse_types::{ConsoleLevel, ConsoleMessage, DialogKind, PageEvent, WaitUntil};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::PBWebviewEvent;
+
+/// Installed in every webview ahead of the consumer's init script.
+/// Posts the page lifecycle and page events that wry doesn't report back to the manager,
+/// and counts in-flight requests so that we can tell when the network is idle.
+pub const LIFECYCLE_SCRIPT: &str = r#"(() => {
+    if (window.top !== window || window.__pagebrowse_network) return;
+
+    const network = { inflight: 0, lastActivity: performance.now() };
+    Object.defineProperty(window, "__pagebrowse_network", { value: network });
+    const begin = () => {
+        network.inflight += 1;
+        network.lastActivity = performance.now();
+    };
+    const end = () => {
+        network.inflight = Math.max(0, network.inflight - 1);
+        network.lastActivity = performance.now();
+    };
+
+    const fetch = window.fetch;
+    window.fetch = function (...args) {
+        begin();
+        return fetch.apply(this, args).finally(end);
+    };
+
+    const send = XMLHttpRequest.prototype.send;
+    XMLHttpRequest.prototype.send = function (...args) {
+        begin();
+        this.addEventListener("loadend", end, { once: true });
+        try {
+            return send.apply(this, args);
+        } catch (e) {
+            end();
+            throw e;
+        }
+    };
+
+    // Images, stylesheets, scripts etc. aren't counted while in flight,
+    // but still mark activity as they finish.
+    new PerformanceObserver(() => {
+        network.lastActivity = performance.now();
+    }).observe({ type: "resource", buffered: true });
+
+    const post = (message) => {
+        try {
+            window.ipc.postMessage(JSON.stringify(message));
+        } catch (e) {}
+    };
+
+    document.addEventListener("DOMContentLoaded", () => {
+        post({ event: "DomContentLoaded", url: location.href });
+    }, { once: true });
+
+    let url = location.href;
+    const checkUrl = () => {
+        if (location.href === url) return;
+        url = location.href;
+        post({ event: "UrlChanged", url });
+    };
+    for (const method of ["pushState", "replaceState"]) {
+        const original = history[method];
+        history[method] = function (...args) {
+            const result = original.apply(this, args);
+            checkUrl();
+            return result;
+        };
+    }
+    window.addEventListener("popstate", checkUrl);
+    window.addEventListener("hashchange", checkUrl);
+
+    const formatValue = (value) => {
+        if (typeof value === "string") return value;
+        if (value instanceof Error) return `${value.name}: ${value.message}`;
+        try {
+            return JSON.stringify(value) ?? String(value);
+        } catch (e) {
+            return String(value);
+        }
+    };
+    // Frames look like `fn@https://example.com/app.js:12:3`, and the
+    // first two are this function and our console wrapper
+    const callerLocation = () => {
+        const frame = (new Error().stack || "").split("\n")[2] || "";
+        const match = /(?:^|@)(.*):(\d+):\d+$/.exec(frame);
+        return match ? { source_url: match[1], line: Number(match[2]) } : { source_url: null, line: null };
+    };
+
+    const levels = { debug: "Debug", log: "Log", info: "Info", warn: "Warn", error: "Error" };
+    for (const [method, level] of Object.entries(levels)) {
+        const original = console[method];
+        console[method] = function (...args) {
+            post({
+                event: "Console",
+                level,
+                text: args.map(formatValue).join(" "),
+                ...callerLocation(),
+            });
+            return original.apply(this, args);
+        };
+    }
+
+    window.addEventListener("error", (e) => {
+        post({
+            event: "PageError",
+            message: e.message,
+            source_url: e.filename || null,
+            line: e.lineno || null,
+            column: e.colno || null,
+            stack: e.error?.stack ?? null,
+        });
+    });
+    window.addEventListener("unhandledrejection", (e) => {
+        post({
+            event: "PageError",
+            message: `Uncaught (in promise) ${formatValue(e.reason)}`,
+            source_url: null,
+            line: null,
+            column: null,
+            stack: e.reason?.stack ?? null,
+        });
+    });
+
+    const dialogs = { alert: "Alert", confirm: "Confirm", prompt: "Prompt" };
+    for (const [method, kind] of Object.entries(dialogs)) {
+        const original = window[method];
+        window[method] = function (message, ...args) {
+            post({ event: "Dialog", kind, message: message === undefined ? "" : String(message) });
+            return original.call(this, message, ...args);
+        };
+    }
+})();"#;
+
+const NETWORK_IDLE_SCRIPT: &str = r#"const network = window.__pagebrowse_network;
+if (network) {
+    await new Promise((resolve) => {
+        const check = () => {
+            const idle = performance.now() - network.lastActivity;
+            if (network.inflight === 0 && idle >= quiet) {
+                resolve();
+            } else {
+                setTimeout(check, Math.max(quiet - idle, 50));
+            }
+        };
+        check();
+    });
+}
+return location.href;"#;
+
+const SELECTOR_SCRIPT: &str = r#"await new Promise((resolve) => {
+    if (document.querySelector(selector)) return resolve();
+    const observer = new MutationObserver(() => {
+        if (document.querySelector(selector)) {
+            observer.disconnect();
+            resolve();
+        }
+    });
+    observer.observe(document, { childList: true, subtree: true, attributes: true });
+});
+return location.href;"#;
+
+/// Messages posted to the manager by `LIFECYCLE_SCRIPT`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "event")]
+pub enum IpcMessage {
+    DomContentLoaded {
+        url: String,
+    },
+    UrlChanged {
+        url: String,
+    },
+    Console {
+        level: ConsoleLevel,
+        text: String,
+        source_url: Option<String>,
+        line: Option<u32>,
+    },
+    PageError {
+        message: String,
+        source_url: Option<String>,
+        line: Option<u32>,
+        column: Option<u32>,
+        stack: Option<String>,
+    },
+    Dialog {
+        kind: DialogKind,
+        message: String,
+    },
+}
+
+impl IpcMessage {
+    pub fn parse(body: &str) -> Option<Self> {
+        serde_json::from_str(body).ok()
+    }
+
+    pub fn into_event(self) -> PBWebviewEvent {
+        match self {
+            IpcMessage::DomContentLoaded { url } => PBWebviewEvent::DomContentLoaded { url },
+            IpcMessage::UrlChanged { url } => PBWebviewEvent::Page(PageEvent::UrlChanged { url }),
+            IpcMessage::Console {
+                level,
+                text,
+                source_url,
+                line,
+            } => PBWebviewEvent::Page(PageEvent::Console(ConsoleMessage {
+                level,
+                text,
+                source_url,
+                line,
+            })),
+            IpcMessage::PageError {
+                message,
+                source_url,
+                line,
+                column,
+                stack,
+            } => PBWebviewEvent::Page(PageEvent::PageError {
+                message,
+                source_url,
+                line,
+                column,
+                stack,
+            }),
+            IpcMessage::Dialog { kind, message } => {
+                PBWebviewEvent::Page(PageEvent::Dialog { kind, message })
+            }
+        }
+    }
+}
+
+/// For wait conditions that have to be checked inside the page, returns a script
+/// (and its arguments) that resolves to the page URL once the condition holds
+pub fn condition_script(wait_until: &WaitUntil) -> Option<(&'static str, Map<String, Value>)> {
+    let mut arguments = Map::new();
+    let script = match wait_until {
+        WaitUntil::DomContentLoaded | WaitUntil::Load => return None,
+        WaitUntil::NetworkIdle(quiet_ms) => {
+            arguments.insert("quiet".into(), Value::from(*quiet_ms));
+            NETWORK_IDLE_SCRIPT
+        }
+        WaitUntil::Selector(selector) => {
+            arguments.insert("selector".into(), Value::from(selector.as_str()));
+            SELECTOR_SCRIPT
+        }
+    };
+
+    Some((script, arguments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifecycle_messages_are_parsed() {
+        let message =
+            IpcMessage::parse(r#"{"event":"DomContentLoaded","url":"https://example.com/"}"#);
+
+        assert!(matches!(
+            message,
+            Some(IpcMessage::DomContentLoaded { url }) if url == "https://example.com/"
+        ));
+    }
+
+    #[test]
+    fn unknown_messages_are_ignored() {
+        for body in [
+            "",
+            "not json",
+            r#"{"url":"https://example.com/"}"#,
+            r#"{"event":"Unknown","url":"https://example.com/"}"#,
+            r#"{"event":"DomContentLoaded"}"#,
+        ] {
+            assert!(
+                IpcMessage::parse(body).is_none(),
+                "{body} should be ignored"
+            );
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::sync::mpsc::{Receiver, Sender};
+
+use tao::event::{Event, StartCause, WindowEvent};
+use tao::event_loop::{ControlFlow, EventLoop, EventLoopProxy};
+
+use crate::platforms::{self, PBPlatform};
+use crate::pool::{handle_event, Pool};
+use crate::transport::{connect_in_process, serve, ListenAddr, Router};
+use crate::{manager_info, InProcessConnection, InitializationParams, PBEvent, PBResponse};
+
+/// A pool of webviews, and the event loop that drives it.
+///
+/// This is what the `pagebrowse_manager` binary runs, and it can also be hosted by an
+/// application that owns its main thread, with the application's `pagebrowse` client
+/// talking to it over `connect` rather than spawning a separate process.
+pub struct Manager {
+    event_loop: EventLoop<Box<PBEvent>>,
+    proxy: EventLoopProxy<Box<PBEvent>>,
+    router: Router,
+    init_rx: Receiver<InitializationParams>,
+    outgoing_tx: Sender<PBResponse>,
+    allow_remote: bool,
+}
+
+impl Manager {
+    /// Sets up the platform's event loop, so must be called on the main thread
+    pub fn new() -> Self {
+        let (outgoing_tx, outgoing_rx) = std::sync::mpsc::channel::<PBResponse>();
+
+        let event_loop = platforms::Platform::setup();
+        let proxy = event_loop.create_proxy();
+
+        let (router, init_rx) = Router::new(proxy.clone(), manager_info());
+        router.route_responses(outgoing_rx);
+
+        Self {
+            event_loop,
+            proxy,
+            router,
+            init_rx,
+            outgoing_tx,
+            allow_remote: false,
+        }
+    }
+
+    /// Lets `listen` take TCP consumers from other hosts. Anyone who can reach the address
+    /// can drive the pool's webviews, as there's no authentication, so only use this on a trusted network.
+    pub fn allow_remote(mut self, allow_remote: bool) -> Self {
+        self.allow_remote = allow_remote;
+        self
+    }
+
+    /// Starts accepting consumers on `addr`, alongside any connected in-process
+    pub fn listen(&self, addr: &ListenAddr) -> std::io::Result<()> {
+        serve(addr, self.allow_remote, self.router.clone())
+    }
+
+    /// Connects a consumer in this process, e.g. with `PagebrowseBuilder::in_process`
+    pub fn connect(&self) -> InProcessConnection {
+        connect_in_process(self.router.clone())
+    }
+
+    /// Returns something that connects consumers to this manager from any thread,
+    /// even once `run` has taken over the main thread
+    pub fn connector(&self) -> impl Fn() -> InProcessConnection + Clone + Send + Sync + 'static {
+        let router = self.router.clone();
+        move || connect_in_process(router.clone())
+    }
+
+    /// Waits for the first consumer to initialize the pool, then runs the event loop
+    /// on this thread until the process exits
+    pub fn run(self) -> ! {
+        let Manager {
+            event_loop,
+            proxy,
+            router,
+            init_rx,
+            outgoing_tx,
+            ..
+        } = self;
+
+        let intial_params = init_rx.recv().expect("Router is running");
+        let mut pool = Pool::new(intial_params, &event_loop, proxy.clone());
+
+        event_loop.run(move |event, _, control_flow| {
+            *control_flow = ControlFlow::Wait;
+
+            match event {
+                Event::UserEvent(evt) => {
+                    handle_event(*evt, &mut pool, outgoing_tx.clone(), proxy.clone());
+                }
+                Event::NewEvents(StartCause::Init) => {
+                    router.start();
+                }
+                Event::WindowEvent {
+                    event: WindowEvent::CloseRequested,
+                    ..
+                } => *control_flow = ControlFlow::Exit,
+                _ => (),
+            };
+
+            #[cfg(target_os = "linux")]
+            while gtk::events_pending() {
+                gtk::main_iteration_do(false);
+            }
+        })
+    }
+}
+
+impl Default for Manager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,23 @@
-use clap::{arg, command, value_parser, ArgMatches};
+use clap::{arg, command, value_parser, ArgAction, ArgMatches};
+
+use crate::transport::ListenAddr;
 
 pub fn get_cli_matches() -> ArgMatches {
-    command!().get_matches()
+    command!()
+        .arg(
+            arg!(--listen <ADDRESS> "Where to take requests from: stdio, unix:<path> or tcp:<host>:<port>. \
+                Consumers connecting to a socket share one pool, configured by the first to initialize it. \
+                Sockets have no authentication, so anyone who can connect can drive the pool's webviews, \
+                though they can't have files written for them. TCP is limited to loopback addresses \
+                unless --allow-remote is passed.")
+                .required(false)
+                .default_value("stdio")
+                .value_parser(value_parser!(ListenAddr)),
+        )
+        .arg(
+            arg!(--"allow-remote" "Accept TCP consumers from other hosts, e.g. when listening on tcp:0.0.0.0:<port>. \
+                Only use this on a trusted network.")
+                .action(ArgAction::SetTrue),
+        )
+        .get_matches()
 }
@@ -1,23 +1,30 @@
 pub use std::path::Path;
 
+use cairo::ImageSurface;
 use gtk::gio::Cancellable;
+use gtk::glib::{DateTime, TimeSpan, ToVariant, VariantDict};
 use gtk::prelude::WidgetExt;
 pub use webkit2gtk::WebContextExt;
 pub use webkit2gtk::WebViewExt;
+use webkit2gtk::{
+    CookieManager, CookieManagerExt, LoadEvent, SnapshotOptions, SnapshotRegion, URIResponseExt,
+    WebProcessTerminationReason, WebResourceExt, WebsiteDataManagerExt,
+    WebsiteDataManagerExtManual, WebsiteDataTypes,
+};
 
+use tao::event_loop::{EventLoop, EventLoopBuilder, EventLoopProxy};
 pub use tao::platform::unix::WindowExtUnix;
-use tao::event_loop::{EventLoop, EventLoopBuilder};
 pub use wry::WebViewExtUnix;
 
 use javascriptcore::ValueExt;
 
-use crate::PBEvent;
+use crate::{script::ScriptException, Capability, Cookie, PBEvent, PBHook, PBWebviewEvent};
+use pagebrowse_types::{PageEvent, SameSite};
 
 pub struct LinuxPlatform {}
 
 impl super::PBPlatform for LinuxPlatform {
     fn setup() -> EventLoop<Box<PBEvent>> {
-        
         gtk::init().unwrap();
         let event_loop = EventLoopBuilder::<Box<PBEvent>>::with_user_event().build();
 
@@ -26,6 +33,17 @@ impl super::PBPlatform for LinuxPlatform {
         event_loop
     }
 
+    fn capabilities() -> Vec<Capability> {
+        vec![
+            Capability::Screenshots,
+            Capability::Cookies,
+            Capability::NavigationStatus,
+            Capability::PageEvents,
+            Capability::CrashEvents,
+            Capability::LengthPrefixedFraming,
+        ]
+    }
+
     fn enhance_webview(webview: &wry::WebView) {
         webview
             .webview()
@@ -34,26 +52,369 @@ impl super::PBPlatform for LinuxPlatform {
             .set_web_extensions_directory("target/debug");
     }
 
-    fn screenshot(webview: &wry::WebView, bytes_callback: impl Fn(&[u8]) -> ()) {
+    fn watch_navigation(
+        webview: &wry::WebView,
+        pool_item: usize,
+        proxy: EventLoopProxy<Box<PBEvent>>,
+    ) {
+        let webview = webview.webview();
+
+        let response_proxy = proxy.clone();
+        webview.connect_load_changed(move |webview, load_event| {
+            // The main resource response is available once the load is committed
+            if load_event != LoadEvent::Committed {
+                return;
+            }
+            let Some(response) = webview.main_resource().and_then(|r| r.response()) else {
+                return;
+            };
+
+            let hook = PBHook {
+                pool_item,
+                event: PBWebviewEvent::ResponseReceived {
+                    url: response.uri().map(|u| u.to_string()).unwrap_or_default(),
+                    // Non-HTTP loads (e.g. about:blank) report a status of 0
+                    status: u16::try_from(response.status_code())
+                        .ok()
+                        .filter(|status| *status > 0),
+                    mime_type: response.mime_type().map(|m| m.to_string()),
+                },
+            };
+            _ = response_proxy.send_event(Box::new(PBEvent::Hook(hook)));
+        });
+
+        let failure_proxy = proxy.clone();
+        webview.connect_load_failed(move |_, _, failing_uri, error| {
+            let hook = PBHook {
+                pool_item,
+                event: PBWebviewEvent::PageLoadFailed {
+                    url: failing_uri.to_string(),
+                    error: error.message().to_string(),
+                },
+            };
+            _ = failure_proxy.send_event(Box::new(PBEvent::Hook(hook)));
+
+            // Let WebKit continue with its default error page
+            false
+        });
+
+        webview.connect_web_process_terminated(move |_, reason| {
+            let reason = match reason {
+                WebProcessTerminationReason::Crashed => "The web process crashed",
+                WebProcessTerminationReason::ExceededMemoryLimit => {
+                    "The web process exceeded its memory limit"
+                }
+                WebProcessTerminationReason::TerminatedByApi => "The web process was terminated",
+                _ => "The web process exited unexpectedly",
+            };
+
+            let hook = PBHook {
+                pool_item,
+                event: PBWebviewEvent::Page(PageEvent::Crashed {
+                    reason: reason.into(),
+                }),
+            };
+            _ = proxy.send_event(Box::new(PBEvent::Hook(hook)));
+        });
+    }
+
+    fn clear_history(webview: &wry::WebView) {
+        // WebKitGTK has no way to clear the back/forward list directly,
+        // but restoring the session of a brand new webview replaces it with an empty one
+        if let Some(empty_session) = webkit2gtk::WebView::new().session_state() {
+            webview.webview().restore_session_state(&empty_session);
+        }
+    }
+
+    fn clear_website_data(
+        webview: &wry::WebView,
+        done_callback: impl FnOnce(Result<(), String>) + Send + 'static,
+    ) {
+        let Some(data_manager) = webview.webview().website_data_manager() else {
+            done_callback(Err("Webview has no website data manager".into()));
+            return;
+        };
+
+        // A timespan of zero clears data regardless of when it was modified
+        data_manager.clear(
+            WebsiteDataTypes::ALL,
+            TimeSpan(0),
+            Cancellable::NONE,
+            move |res| done_callback(res.map_err(|e| e.to_string())),
+        );
+    }
+
+    fn get_cookies(
+        webview: &wry::WebView,
+        url: Option<&str>,
+        cookies_callback: impl FnOnce(Result<Vec<Cookie>, String>) + 'static,
+    ) {
+        let cookie_manager = match cookie_manager(webview) {
+            Ok(cookie_manager) => cookie_manager,
+            Err(e) => return cookies_callback(Err(e)),
+        };
+
+        let Some(url) = url else {
+            return all_cookies(webview, cookie_manager, cookies_callback);
+        };
+
+        cookie_manager.cookies(url, Cancellable::NONE, move |res| {
+            cookies_callback(
+                res.map(|cookies| cookies.into_iter().map(from_soup_cookie).collect())
+                    .map_err(|e| e.to_string()),
+            )
+        });
+    }
+
+    fn set_cookies(
+        webview: &wry::WebView,
+        cookies: Vec<Cookie>,
+        done_callback: impl FnOnce(Result<(), String>) + 'static,
+    ) {
+        match cookie_manager(webview) {
+            Ok(cookie_manager) => add_cookies(cookie_manager, cookies, done_callback),
+            Err(e) => done_callback(Err(e)),
+        }
+    }
+
+    fn clear_cookies(
+        webview: &wry::WebView,
+        done_callback: impl FnOnce(Result<(), String>) + 'static,
+    ) {
+        let Some(data_manager) = webview.webview().website_data_manager() else {
+            return done_callback(Err("Webview has no website data manager".into()));
+        };
+
+        data_manager.clear(
+            WebsiteDataTypes::COOKIES,
+            TimeSpan(0),
+            Cancellable::NONE,
+            move |res| done_callback(res.map_err(|e| e.to_string())),
+        );
+    }
+
+    fn screenshot(
+        webview: &wry::WebView,
+        full_page: bool,
+        bytes_callback: impl Fn(Result<&[u8], String>) -> () + 'static,
+    ) {
         // Linux screenshotting
-        // if let Some(window) = webview.window().gtk_window().window() {
-        //     let inner_size = webview.window().inner_size();
-        //     window
-        //         .pixbuf(0, 0, inner_size.width as i32, inner_size.height as i32)
-        //         .unwrap()
-        //         .savev(Path::new("/workspace/test.jpg"), "jpeg", &[]);
-        // }
-    }
-
-    fn run_js(webview: &wry::WebView, js: &str, output_callback: impl Fn(String) -> () + 'static) {
-        webview.webview().call_async_javascript_function(js, None, None, None, Cancellable::NONE, move |res| {
-            let mut result = String::new();
-            if let Ok(output) = res {
-                if let Some(json) = output.to_json(0) {
-                    result = json.to_string();
+        let region = if full_page {
+            SnapshotRegion::FullDocument
+        } else {
+            SnapshotRegion::Visible
+        };
+
+        webview.webview().snapshot(
+            region,
+            SnapshotOptions::NONE,
+            Cancellable::NONE,
+            move |res| {
+                let png = res.map_err(|e| e.to_string()).and_then(|surface| {
+                    let surface = ImageSurface::try_from(surface)
+                        .map_err(|_| "Webview snapshot was not an image surface".to_string())?;
+
+                    let mut png = vec![];
+                    surface
+                        .write_to_png(&mut png)
+                        .map_err(|e| format!("Failed to encode snapshot as PNG: {e}"))?;
+                    Ok(png)
+                });
+
+                match png {
+                    Ok(png) => bytes_callback(Ok(&png)),
+                    Err(e) => bytes_callback(Err(e)),
+                }
+            },
+        );
+    }
+
+    fn run_js(
+        webview: &wry::WebView,
+        js: &str,
+        arguments: &serde_json::Map<String, serde_json::Value>,
+        output_callback: impl Fn(Result<String, ScriptException>) -> () + 'static,
+    ) {
+        // WebKitGTK only converts numbers, strings and dictionaries to JavaScript,
+        // so each argument is passed as a JSON string and parsed back before the script runs.
+        // The prologue shares the first line of the script to keep line numbers intact.
+        let args = VariantDict::new(None);
+        let mut prologue = String::new();
+        for (name, value) in arguments {
+            args.insert_value(name, &value.to_string().to_variant());
+            prologue.push_str(&format!("{name} = JSON.parse({name}); "));
+        }
+        let js = format!("{prologue}{js}");
+
+        webview.webview().call_async_javascript_function(
+            &js,
+            Some(&args.end()),
+            None,
+            None,
+            Cancellable::NONE,
+            move |res| match res {
+                Ok(output) => {
+                    let result = output
+                        .to_json(0)
+                        .map(|json| json.to_string())
+                        .unwrap_or_default();
+                    output_callback(Ok(result));
                 }
+                Err(e) => output_callback(Err(ScriptException::from_message(e.message()))),
+            },
+        );
+    }
+}
+
+/// The cookie manager for the webview's own data store,
+/// which isn't shared with the rest of the pool if sessions are isolated
+fn cookie_manager(webview: &wry::WebView) -> Result<CookieManager, String> {
+    webview
+        .webview()
+        .website_data_manager()
+        .and_then(|data_manager| data_manager.cookie_manager())
+        .ok_or_else(|| "Webview has no cookie manager".to_string())
+}
+
+/// WebKitGTK can't list every cookie directly, so this asks for the cookies that would be sent
+/// to the root of each domain that has any, over both HTTP and HTTPS, and to the current page.
+/// Cookies that are only sent to other subdomains, or to other paths, are still missed.
+fn all_cookies(
+    webview: &wry::WebView,
+    cookie_manager: CookieManager,
+    cookies_callback: impl FnOnce(Result<Vec<Cookie>, String>) + 'static,
+) {
+    let Some(data_manager) = webview.webview().website_data_manager() else {
+        return cookies_callback(Err("Webview has no website data manager".into()));
+    };
+    // Covers host-only cookies from a subdomain, and path scoped cookies, for the page in view
+    let current_url = webview
+        .webview()
+        .uri()
+        .map(|uri| uri.to_string())
+        .filter(|uri| uri.starts_with("http://") || uri.starts_with("https://"));
+
+    data_manager.fetch(
+        WebsiteDataTypes::COOKIES,
+        Cancellable::NONE,
+        move |res| match res {
+            Ok(website_data) => {
+                let urls = website_data
+                    .iter()
+                    .filter_map(|data| data.name())
+                    .flat_map(|domain| [format!("https://{domain}/"), format!("http://{domain}/")])
+                    .chain(current_url)
+                    .collect();
+                collect_cookies(cookie_manager, urls, vec![], cookies_callback);
             }
-            output_callback(result);
+            Err(e) => cookies_callback(Err(e.to_string())),
+        },
+    );
+}
+
+/// Fetches the cookies for each URL in turn, skipping any we've already seen
+fn collect_cookies(
+    cookie_manager: CookieManager,
+    mut urls: Vec<String>,
+    mut cookies: Vec<Cookie>,
+    cookies_callback: impl FnOnce(Result<Vec<Cookie>, String>) + 'static,
+) {
+    let Some(url) = urls.pop() else {
+        return cookies_callback(Ok(cookies));
+    };
+
+    cookie_manager
+        .clone()
+        .cookies(&url, Cancellable::NONE, move |res| match res {
+            Ok(found) => {
+                for cookie in found.into_iter().map(from_soup_cookie) {
+                    if !cookies.contains(&cookie) {
+                        cookies.push(cookie);
+                    }
+                }
+                collect_cookies(cookie_manager, urls, cookies, cookies_callback);
+            }
+            Err(e) => cookies_callback(Err(e.to_string())),
+        });
+}
+
+/// Adds each cookie in turn, as the cookie manager only takes one at a time
+fn add_cookies(
+    cookie_manager: CookieManager,
+    mut cookies: Vec<Cookie>,
+    done_callback: impl FnOnce(Result<(), String>) + 'static,
+) {
+    let Some(cookie) = cookies.pop() else {
+        return done_callback(Ok(()));
+    };
+
+    let mut soup_cookie = match to_soup_cookie(&cookie) {
+        Ok(soup_cookie) => soup_cookie,
+        Err(e) => return done_callback(Err(e)),
+    };
+
+    cookie_manager
+        .clone()
+        .add_cookie(&mut soup_cookie, Cancellable::NONE, move |res| match res {
+            Ok(()) => add_cookies(cookie_manager, cookies, done_callback),
+            Err(e) => done_callback(Err(format!("Failed to set cookie `{}`: {e}", cookie.name))),
         });
+}
+
+fn to_soup_cookie(cookie: &Cookie) -> Result<soup::Cookie, String> {
+    // A max age of -1 makes a session cookie
+    let mut soup_cookie = soup::Cookie::new(
+        &cookie.name,
+        &cookie.value,
+        &cookie.domain,
+        &cookie.path,
+        -1,
+    );
+
+    if let Some(expires) = cookie.expires {
+        let expires = DateTime::from_unix_utc(expires)
+            .map_err(|_| format!("Cookie `{}` has an invalid expiry", cookie.name))?;
+        soup_cookie.set_expires(&expires);
+    }
+    soup_cookie.set_http_only(cookie.http_only);
+    soup_cookie.set_secure(cookie.secure);
+    if let Some(same_site) = cookie.same_site {
+        soup_cookie.set_same_site_policy(match same_site {
+            SameSite::None => soup::SameSitePolicy::None,
+            SameSite::Lax => soup::SameSitePolicy::Lax,
+            SameSite::Strict => soup::SameSitePolicy::Strict,
+        });
+    }
+
+    Ok(soup_cookie)
+}
+
+fn from_soup_cookie(mut soup_cookie: soup::Cookie) -> Cookie {
+    Cookie {
+        name: soup_cookie
+            .name()
+            .map(|n| n.to_string())
+            .unwrap_or_default(),
+        value: soup_cookie
+            .value()
+            .map(|v| v.to_string())
+            .unwrap_or_default(),
+        domain: soup_cookie
+            .domain()
+            .map(|d| d.to_string())
+            .unwrap_or_default(),
+        path: soup_cookie
+            .path()
+            .map(|p| p.to_string())
+            .unwrap_or_default(),
+        expires: soup_cookie.expires().map(|expires| expires.to_unix()),
+        http_only: soup_cookie.is_http_only(),
+        secure: soup_cookie.is_secure(),
+        same_site: match soup_cookie.same_site_policy() {
+            soup::SameSitePolicy::Lax => Some(SameSite::Lax),
+            soup::SameSitePolicy::Strict => Some(SameSite::Strict),
+            soup::SameSitePolicy::None => Some(SameSite::None),
+            _ => None,
+        },
     }
 }
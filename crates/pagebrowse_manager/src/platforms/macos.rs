@@ -1,8 +1,11 @@
-pub use std::{ffi::c_char, slice, str};
+pub use std::{cell::Cell, ffi::c_char, rc::Rc, slice, str};
 
 pub use block::ConcreteBlock;
 pub use cocoa::base::{id, NO, YES};
-use cocoa::{base::nil, foundation::NSArray};
+use cocoa::{
+    base::nil,
+    foundation::{NSArray, NSRect, NSSize},
+};
 pub use objc::{
     class,
     declare::ClassDecl,
@@ -12,12 +15,12 @@ pub use objc::{
 };
 
 use tao::{
-    event_loop::{EventLoop, EventLoopBuilder},
+    event_loop::{EventLoop, EventLoopBuilder, EventLoopProxy},
     platform::macos::EventLoopExtMacOS,
 };
 pub use wry::WebViewExtMacOS;
 
-use crate::PBEvent;
+use crate::{script::ScriptException, Capability, Cookie, PBEvent};
 
 pub struct MacOSPlatform {}
 
@@ -33,39 +36,152 @@ impl super::PBPlatform for MacOSPlatform {
         event_loop
     }
 
+    fn capabilities() -> Vec<Capability> {
+        vec![
+            Capability::Screenshots,
+            Capability::PageEvents,
+            Capability::LengthPrefixedFraming,
+        ]
+    }
+
     fn enhance_webview(_webview: &wry::WebView) {
         /* no-op */
     }
 
-    fn screenshot(webview: &wry::WebView, bytes_callback: impl Fn(&[u8]) -> ()) {
-        // MacOS screenshotting
+    fn watch_navigation(
+        _webview: &wry::WebView,
+        _pool_item: usize,
+        _proxy: EventLoopProxy<Box<PBEvent>>,
+    ) {
+        // TODO: Report failures, status codes and crashes through a WKNavigationDelegate
+    }
+
+    fn clear_history(webview: &wry::WebView) {
         unsafe {
-            let webview: id = webview.webview();
-            let block = ConcreteBlock::new(move |image: id, _error: id| {
-                let image_data: id = msg_send![image, TIFFRepresentation];
-                let image_rep: id = msg_send![class!(NSBitmapImageRep), alloc];
-                let image_rep: id = msg_send![image_rep, initWithData:image_data];
-                let image_data: id = msg_send![image_rep, representationUsingType:4 properties:nil];
+            let wk_webview: id = webview.webview();
+            let back_forward_list: id = msg_send![wk_webview, backForwardList];
+
+            // WKBackForwardList has no public way to clear itself
+            let remove_all_items = sel!(_removeAllItems);
+            let supported: BOOL =
+                msg_send![back_forward_list, respondsToSelector: remove_all_items];
+            if supported == YES {
+                let _: () = msg_send![back_forward_list, performSelector: remove_all_items];
+            }
+        }
+    }
 
-                let len = msg_send![image_data, length];
-                let byte_ptr: *const u8 = msg_send![image_data, bytes];
-                let bytes = slice::from_raw_parts(byte_ptr, len);
+    fn clear_website_data(
+        webview: &wry::WebView,
+        done_callback: impl FnOnce(Result<(), String>) + Send + 'static,
+    ) {
+        unsafe {
+            let wk_webview: id = webview.webview();
+            let configuration: id = msg_send![wk_webview, configuration];
+            let data_store: id = msg_send![configuration, websiteDataStore];
 
-                bytes_callback(bytes);
+            let data_types: id = msg_send![class!(WKWebsiteDataStore), allWebsiteDataTypes];
+            let since: id = msg_send![class!(NSDate), distantPast];
+
+            let done_callback = Cell::new(Some(done_callback));
+            let block = ConcreteBlock::new(move || {
+                if let Some(done_callback) = done_callback.take() {
+                    done_callback(Ok(()));
+                }
             });
-            let conf: id = msg_send![class!(WKSnapshotConfiguration), alloc];
-            let conf: id = msg_send![conf, init];
-            let _: () =
-                msg_send![webview, takeSnapshotWithConfiguration: conf completionHandler: block];
+
+            let _: () = msg_send![data_store, removeDataOfTypes:data_types modifiedSince:since completionHandler:block];
+        }
+    }
+
+    fn get_cookies(
+        _webview: &wry::WebView,
+        _url: Option<&str>,
+        cookies_callback: impl FnOnce(Result<Vec<Cookie>, String>) + 'static,
+    ) {
+        // TODO: Read cookies through the data store's WKHTTPCookieStore
+        cookies_callback(Err("Cookies are not yet supported on macOS".into()));
+    }
+
+    fn set_cookies(
+        _webview: &wry::WebView,
+        _cookies: Vec<Cookie>,
+        done_callback: impl FnOnce(Result<(), String>) + 'static,
+    ) {
+        // TODO: Write cookies through the data store's WKHTTPCookieStore
+        done_callback(Err("Cookies are not yet supported on macOS".into()));
+    }
+
+    fn clear_cookies(
+        _webview: &wry::WebView,
+        done_callback: impl FnOnce(Result<(), String>) + 'static,
+    ) {
+        // TODO: Remove cookies through the data store's WKHTTPCookieStore
+        done_callback(Err("Cookies are not yet supported on macOS".into()));
+    }
+
+    fn screenshot(
+        webview: &wry::WebView,
+        full_page: bool,
+        bytes_callback: impl Fn(Result<&[u8], String>) -> () + 'static,
+    ) {
+        // MacOS screenshotting
+        let wk_webview: id = webview.webview();
+
+        if !full_page {
+            unsafe { take_snapshot(wk_webview, bytes_callback) };
+            return;
         }
+
+        // WKWebView only snapshots its own bounds, so for a full page capture
+        // we grow the view to the document size for the duration of the snapshot.
+        let bytes_callback = Rc::new(bytes_callback);
+        <Self as super::PBPlatform>::run_js(
+            webview,
+            "return [document.documentElement.scrollWidth, document.documentElement.scrollHeight];",
+            &serde_json::Map::new(),
+            move |output| {
+                let Some([width, height]) = output
+                    .ok()
+                    .and_then(|output| serde_json::from_str::<[f64; 2]>(&output).ok())
+                else {
+                    bytes_callback(Err(
+                        "Unable to measure the document for a full page screenshot".into(),
+                    ));
+                    return;
+                };
+
+                let bytes_callback = bytes_callback.clone();
+                unsafe {
+                    let original_frame: NSRect = msg_send![wk_webview, frame];
+                    let full_frame = NSRect::new(original_frame.origin, NSSize::new(width, height));
+                    let _: () = msg_send![wk_webview, setFrame: full_frame];
+
+                    take_snapshot(wk_webview, move |bytes| {
+                        let _: () = msg_send![wk_webview, setFrame: original_frame];
+                        bytes_callback(bytes);
+                    });
+                }
+            },
+        );
     }
 
-    fn run_js(webview: &wry::WebView, js: &str, output_callback: impl Fn(String) -> ()) {
+    fn run_js(
+        webview: &wry::WebView,
+        js: &str,
+        arguments: &serde_json::Map<String, serde_json::Value>,
+        output_callback: impl Fn(Result<String, ScriptException>) -> () + 'static,
+    ) {
         // MacOS javascript evaluation
         unsafe {
             let webview: id = webview.webview();
 
-            let block = ConcreteBlock::new(move |output_value: id, _error: id| {
+            let block = ConcreteBlock::new(move |output_value: id, error: id| {
+                if error != nil {
+                    output_callback(Err(script_exception_from_error(error)));
+                    return;
+                }
+
                 let mut result = String::new();
 
                 if output_value != nil {
@@ -76,18 +192,83 @@ impl super::PBPlatform for MacOSPlatform {
                     result = json_string.to_str().to_string();
                 }
 
-                output_callback(result)
+                output_callback(Ok(result))
             });
 
             let content_world: id = msg_send![class!(WKContentWorld), pageWorld];
 
-            let args: id = msg_send![class!(NSDictionary), dictionary];
+            let arguments = serde_json::to_vec(arguments).expect("JSON map is serializable");
+            let arguments: id =
+                msg_send![class!(NSData), dataWithBytes:arguments.as_ptr() length:arguments.len()];
+            let args: id = msg_send![class!(NSJSONSerialization), JSONObjectWithData:arguments options:0u64 error:nil];
 
             let _: () = msg_send![webview, callAsyncJavaScript:NSString::new(js) arguments:args inFrame:nil inContentWorld:content_world completionHandler:block];
         }
     }
 }
 
+/// Reads the exception details WebKit attaches to a failed script evaluation
+unsafe fn script_exception_from_error(error: id) -> ScriptException {
+    let user_info: id = msg_send![error, userInfo];
+
+    let value_for_key = |key: &str| -> id {
+        if user_info == nil {
+            return nil;
+        }
+        msg_send![user_info, objectForKey: NSString::new(key).as_ptr()]
+    };
+    let number_for_key = |key: &str| -> Option<u32> {
+        let number = value_for_key(key);
+        if number == nil {
+            return None;
+        }
+        let number: i64 = msg_send![number, integerValue];
+        u32::try_from(number).ok()
+    };
+
+    let exception_message = value_for_key("WKJavaScriptExceptionMessage");
+    let message = if exception_message != nil {
+        NSString(exception_message).to_str().to_string()
+    } else {
+        let description: id = msg_send![error, localizedDescription];
+        NSString(description).to_str().to_string()
+    };
+
+    ScriptException {
+        message,
+        line: number_for_key("WKJavaScriptExceptionLineNumber"),
+        column: number_for_key("WKJavaScriptExceptionColumnNumber"),
+        stack: None,
+    }
+}
+
+unsafe fn take_snapshot(
+    webview: id,
+    bytes_callback: impl Fn(Result<&[u8], String>) -> () + 'static,
+) {
+    let block = ConcreteBlock::new(move |image: id, error: id| {
+        if image == nil {
+            let description: id = msg_send![error, localizedDescription];
+            bytes_callback(Err(NSString(description).to_str().to_string()));
+            return;
+        }
+
+        let image_data: id = msg_send![image, TIFFRepresentation];
+        let image_rep: id = msg_send![class!(NSBitmapImageRep), alloc];
+        let image_rep: id = msg_send![image_rep, initWithData:image_data];
+        let image_data: id = msg_send![image_rep, representationUsingType:4 properties:nil];
+
+        let len = msg_send![image_data, length];
+        let byte_ptr: *const u8 = msg_send![image_data, bytes];
+        let bytes = slice::from_raw_parts(byte_ptr, len);
+
+        bytes_callback(Ok(bytes));
+    });
+    let conf: id = msg_send![class!(WKSnapshotConfiguration), alloc];
+    let conf: id = msg_send![conf, init];
+    let _: () = msg_send![webview, takeSnapshotWithConfiguration: conf completionHandler: block];
+}
+
 pub fn setup_macos() {
     //====== NSURLProtocol Methods =========
 
@@ -20,13 +20,54 @@ pub use macos::MacOSPlatform as Platform;
     target_os = "openbsd"
 ))]
 pub use linux::LinuxPlatform as Platform;
-use tao::event_loop::EventLoop;
+use tao::event_loop::{EventLoop, EventLoopProxy};
 
-use crate::PBEvent;
+use crate::{script::ScriptException, Capability, Cookie, PBEvent};
 
 pub trait PBPlatform {
     fn setup() -> EventLoop<Box<PBEvent>>;
+    /// The optional features this platform implements, to report in the handshake
+    fn capabilities() -> Vec<Capability>;
     fn enhance_webview(webview: &wry::WebView);
-    fn screenshot(webview: &wry::WebView, bytes_callback: impl Fn(&[u8]) -> ());
-    fn run_js(webview: &wry::WebView, js: &str, output_callback: impl Fn(String) -> () + 'static);
+    /// Reports load failures, main resource responses and crashes as hooks,
+    /// which wry's page load handler doesn't provide
+    fn watch_navigation(
+        webview: &wry::WebView,
+        pool_item: usize,
+        proxy: EventLoopProxy<Box<PBEvent>>,
+    );
+    /// Empties the webview's back/forward list
+    fn clear_history(webview: &wry::WebView);
+    /// Clears cookies, storage and caches from the webview's website data store
+    fn clear_website_data(
+        webview: &wry::WebView,
+        done_callback: impl FnOnce(Result<(), String>) + Send + 'static,
+    );
+    /// Fetches the cookies that the webview would send with a request to `url`,
+    /// or all of the webview's cookies if no `url` is given
+    fn get_cookies(
+        webview: &wry::WebView,
+        url: Option<&str>,
+        cookies_callback: impl FnOnce(Result<Vec<Cookie>, String>) + 'static,
+    );
+    fn set_cookies(
+        webview: &wry::WebView,
+        cookies: Vec<Cookie>,
+        done_callback: impl FnOnce(Result<(), String>) + 'static,
+    );
+    fn clear_cookies(
+        webview: &wry::WebView,
+        done_callback: impl FnOnce(Result<(), String>) + 'static,
+    );
+    fn screenshot(
+        webview: &wry::WebView,
+        full_page: bool,
+        bytes_callback: impl Fn(Result<&[u8], String>) -> () + 'static,
+    );
+    fn run_js(
+        webview: &wry::WebView,
+        js: &str,
+        arguments: &serde_json::Map<String, serde_json::Value>,
+        output_callback: impl Fn(Result<String, ScriptException>) -> () + 'static,
+    );
 }
@@ -0,0 +1,1044 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use crate::lifecycle::{condition_script, IpcMessage, LIFECYCLE_SCRIPT};
+use crate::platforms;
+use crate::platforms::PBPlatform;
+use crate::screenshot::process_screenshot;
+use crate::script::{is_valid_argument_name, parse_script_output, wrap_script, ScriptException};
+use crate::storage::restore_local_storage_script;
+use crate::InitializationParams;
+use crate::NavigationResult;
+use crate::PBEvent;
+use crate::PBHook;
+use crate::PBRequest;
+use crate::PBRequestPayload;
+use crate::PBResponse;
+use crate::PBResponsePayload;
+use crate::PBWebviewEvent;
+use crate::PageEvent;
+use crate::StorageState;
+use crate::WaitUntil;
+use crate::WindowReset;
+use tao::dpi::PhysicalPosition;
+use tao::dpi::Position;
+use tao::event_loop::EventLoopProxy;
+use tao::window::Window;
+use wry::PageLoadEvent;
+use wry::WebView;
+
+use tao::dpi::PhysicalSize;
+use tao::event_loop::EventLoop;
+use tao::window::WindowBuilder;
+use wry::WebViewBuilder;
+
+#[cfg(target_os = "linux")]
+use tao::platform::unix::WindowExtUnix;
+#[cfg(target_os = "linux")]
+use wry::WebViewBuilderExtUnix;
+
+/// How long a released webview gets to load its blank page before the reset carries on without it
+const BLANK_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum PoolEvent {
+    PageLoad { inner: PageLoadEvent, url: String },
+}
+
+struct PoolItem {
+    id: usize,
+    window: Window,
+    webview: WebView,
+    assigned_to: Option<u32>,
+    /// Incremented each time we start a navigation in this webview
+    latest_navigation: u64,
+    /// The navigation that the webview's most recent page load belongs to
+    loading_navigation: Option<u64>,
+    pending_navigation: Option<PendingNavigation>,
+    current_load: LoadDetails,
+    /// Incremented each time we start checking a wait condition in the page
+    latest_check: u64,
+    /// Whether the webview is still being reset or loaded with storage state,
+    /// and so can't be assigned yet
+    preparing: bool,
+    /// Whether the webview is loading `about:blank` after being released,
+    /// so its page loads are part of the reset rather than anyone's navigation
+    blanking: bool,
+    /// Incremented each time the webview starts blanking,
+    /// so a timeout can tell whether it's for the current reset
+    latest_reset: u64,
+}
+
+/// What we've learned about the webview's most recent page load
+#[derive(Clone, Default)]
+struct LoadDetails {
+    status: Option<u16>,
+    mime_type: Option<String>,
+    error: Option<String>,
+}
+
+/// A navigate request that responds once its wait condition holds
+struct PendingNavigation {
+    navigation_id: u64,
+    message_id: u32,
+    wait_until: WaitUntil,
+    /// The in-page condition check running for the current page, if any
+    check: Option<u64>,
+}
+
+#[derive(Debug)]
+enum PoolError {
+    /// The window was never assigned, or has since been released
+    UnknownWindow(u32),
+}
+
+impl std::fmt::Display for PoolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PoolError::UnknownWindow(window_id) => write!(
+                f,
+                "Window {window_id} does not exist, or has already been released"
+            ),
+        }
+    }
+}
+
+struct WindowReference {
+    pool_index: usize,
+}
+
+pub(crate) struct Pool {
+    items: Vec<PoolItem>,
+    assignments: HashMap<u32, WindowReference>,
+    next_assignment: u32,
+    waiting_for_windows: VecDeque<u32>,
+    window_reset: WindowReset,
+    /// Loaded into each webview when it's created, and again after its data is cleared
+    storage_state: Option<StorageState>,
+}
+
+impl Pool {
+    pub(crate) fn new(
+        params: InitializationParams,
+        event_loop: &EventLoop<Box<PBEvent>>,
+        proxy: EventLoopProxy<Box<PBEvent>>,
+    ) -> Self {
+        let restore_storage_script = params
+            .storage_state
+            .as_ref()
+            .filter(|storage_state| !storage_state.origins.is_empty())
+            .map(|storage_state| restore_local_storage_script(&storage_state.origins));
+
+        let pool_items: Vec<PoolItem> = (0..params.pool_size)
+            .map(|i| {
+                let window = WindowBuilder::new()
+                    .with_visible(params.visible)
+                    .build(&event_loop)
+                    .expect("Window should be created");
+
+                let this_proxy = proxy.clone();
+                let ipc_proxy = proxy.clone();
+
+                #[cfg(target_os = "macos")]
+                let mut builder = WebViewBuilder::new(&window);
+
+                #[cfg(any(
+                    target_os = "linux",
+                    target_os = "dragonfly",
+                    target_os = "freebsd",
+                    target_os = "netbsd",
+                    target_os = "openbsd"
+                ))]
+                let mut builder = {
+                    let vbox = window.default_vbox().unwrap();
+                    WebViewBuilder::new_gtk(vbox)
+                };
+
+                builder = builder
+                    .with_navigation_handler(move |url| {
+                        // eprintln!("Webview {i} is navigating to {url}");
+                        true
+                    })
+                    .with_on_page_load_handler(move |inner, url| {
+                        let hook = match inner {
+                            PageLoadEvent::Started => PBHook {
+                                pool_item: i,
+                                event: PBWebviewEvent::PageLoadStart { url },
+                            },
+                            PageLoadEvent::Finished => PBHook {
+                                pool_item: i,
+                                event: PBWebviewEvent::PageLoadFinish { url },
+                            },
+                        };
+
+                        if this_proxy
+                            .send_event(Box::new(PBEvent::Hook(hook)))
+                            .is_err()
+                        {
+                            panic!("todo");
+                        };
+                    })
+                    .with_ipc_handler(move |request| {
+                        let Some(message) = IpcMessage::parse(request.body()) else {
+                            return;
+                        };
+
+                        let hook = PBHook {
+                            pool_item: i,
+                            event: message.into_event(),
+                        };
+                        _ = ipc_proxy.send_event(Box::new(PBEvent::Hook(hook)));
+                    })
+                    .with_initialization_script(LIFECYCLE_SCRIPT)
+                    // Incognito webviews each get their own ephemeral context and data store
+                    .with_incognito(params.isolated_sessions);
+
+                if let Some(js) = &restore_storage_script {
+                    builder = builder.with_initialization_script(js);
+                }
+
+                if let Some(js) = &params.init_script {
+                    builder = builder.with_initialization_script(&js);
+                }
+
+                let webview = builder.build().expect("Webview should create successfully");
+
+                platforms::Platform::enhance_webview(&webview);
+                platforms::Platform::watch_navigation(&webview, i, proxy.clone());
+
+                PoolItem {
+                    id: i,
+                    window,
+                    webview,
+                    assigned_to: None,
+                    latest_navigation: 0,
+                    loading_navigation: None,
+                    pending_navigation: None,
+                    current_load: LoadDetails::default(),
+                    latest_check: 0,
+                    preparing: false,
+                    blanking: false,
+                    latest_reset: 0,
+                }
+            })
+            .collect();
+
+        let mut pool = Self {
+            items: pool_items,
+            assignments: HashMap::new(),
+            next_assignment: 0,
+            waiting_for_windows: VecDeque::new(),
+            window_reset: params.window_reset,
+            storage_state: params.storage_state,
+        };
+
+        for pool_index in 0..pool.items.len() {
+            pool.load_storage_state(pool_index, proxy.clone());
+        }
+
+        pool
+    }
+
+    fn get_assigned_window(&mut self, window_id: u32) -> Result<&mut PoolItem, PoolError> {
+        let Some(window_assignment) = self.assignments.get(&window_id) else {
+            return Err(PoolError::UnknownWindow(window_id));
+        };
+
+        let window_in_pool = self.items.get_mut(window_assignment.pool_index).unwrap();
+
+        Ok(window_in_pool)
+    }
+
+    fn release_assigned_window(&mut self, window_id: u32) {
+        self.assignments.remove(&window_id);
+    }
+
+    fn assign_window(&mut self, pool_index: usize) -> u32 {
+        let window_id = self.next_assignment;
+        self.next_assignment += 1;
+
+        self.assignments
+            .insert(window_id, WindowReference { pool_index });
+
+        self.items.get_mut(pool_index).unwrap().assigned_to = Some(window_id);
+
+        window_id
+    }
+
+    /// Cleans up a released pool item as configured,
+    /// then hands it to anyone waiting for a window
+    fn reset_window(
+        &mut self,
+        pool_index: usize,
+        outgoing_tx: &Sender<PBResponse>,
+        proxy: EventLoopProxy<Box<PBEvent>>,
+    ) {
+        let item = self.items.get_mut(pool_index).unwrap();
+
+        if self.window_reset != WindowReset::None {
+            platforms::Platform::clear_history(&item.webview);
+            match item.webview.load_url("about:blank") {
+                // The rest of the reset waits until the blank page has finished loading
+                Ok(()) => {
+                    item.preparing = true;
+                    item.blanking = true;
+                    item.latest_reset += 1;
+
+                    // Don't lose the window if the blank page never finishes loading
+                    let reset_id = item.latest_reset;
+                    std::thread::spawn(move || {
+                        std::thread::sleep(BLANK_TIMEOUT);
+                        let hook = PBHook {
+                            pool_item: pool_index,
+                            event: PBWebviewEvent::BlankTimedOut { reset_id },
+                        };
+                        _ = proxy.send_event(Box::new(PBEvent::Hook(hook)));
+                    });
+                    return;
+                }
+                Err(e) => eprintln!("Failed to blank webview {pool_index}: {e}"),
+            }
+        }
+
+        self.finish_reset(pool_index, outgoing_tx, proxy);
+    }
+
+    /// Clears the released pool item's website data if configured to,
+    /// then hands it to anyone waiting for a window
+    fn finish_reset(
+        &mut self,
+        pool_index: usize,
+        outgoing_tx: &Sender<PBResponse>,
+        proxy: EventLoopProxy<Box<PBEvent>>,
+    ) {
+        let item = self.items.get_mut(pool_index).unwrap();
+
+        if self.window_reset == WindowReset::Full {
+            item.preparing = true;
+            platforms::Platform::clear_website_data(&item.webview, move |res| {
+                let hook = PBHook {
+                    pool_item: pool_index,
+                    event: PBWebviewEvent::ResetComplete { error: res.err() },
+                };
+                _ = proxy.send_event(Box::new(PBEvent::Hook(hook)));
+            });
+            return;
+        }
+
+        item.preparing = false;
+        self.hand_out_window(pool_index, outgoing_tx);
+    }
+
+    /// Adds the storage state's cookies to a webview, holding it back from being assigned
+    /// until they're in place. Returns false if there are no cookies to load.
+    fn load_storage_state(
+        &mut self,
+        pool_index: usize,
+        proxy: EventLoopProxy<Box<PBEvent>>,
+    ) -> bool {
+        let Some(storage_state) = &self.storage_state else {
+            return false;
+        };
+        if storage_state.cookies.is_empty() {
+            return false;
+        }
+
+        let item = self.items.get_mut(pool_index).unwrap();
+        item.preparing = true;
+        platforms::Platform::set_cookies(
+            &item.webview,
+            storage_state.cookies.clone(),
+            move |res| {
+                let hook = PBHook {
+                    pool_item: pool_index,
+                    event: PBWebviewEvent::StorageStateLoaded { error: res.err() },
+                };
+                _ = proxy.send_event(Box::new(PBEvent::Hook(hook)));
+            },
+        );
+
+        true
+    }
+
+    /// Assigns a free pool item to the longest waiting `NewWindow` request, if there is one
+    fn hand_out_window(&mut self, pool_index: usize, outgoing_tx: &Sender<PBResponse>) {
+        let Some(waiting) = self.waiting_for_windows.pop_front() else {
+            return;
+        };
+
+        let assigned_to = self.assign_window(pool_index);
+
+        outgoing_tx
+            .send(PBResponse {
+                message_id: Some(waiting),
+                payload: PBResponsePayload::NewWindowCreated { id: assigned_to },
+            })
+            .expect("handle this error one day");
+    }
+}
+
+pub(crate) fn handle_event(
+    evt: PBEvent,
+    pool: &mut Pool,
+    outgoing_tx: Sender<PBResponse>,
+    proxy: EventLoopProxy<Box<PBEvent>>,
+) {
+    match evt {
+        PBEvent::Request(msg) => handle_message(msg, pool, outgoing_tx, proxy),
+        PBEvent::Hook(hook) => handle_hook(hook, pool, outgoing_tx, proxy),
+    }
+}
+
+fn handle_hook(
+    hook: PBHook,
+    pool: &mut Pool,
+    outgoing_tx: Sender<PBResponse>,
+    proxy: EventLoopProxy<Box<PBEvent>>,
+) {
+    let window_in_pool = pool
+        .items
+        .get_mut(hook.pool_item)
+        .expect("Pool is behaving");
+
+    match hook.event {
+        // A cancelled load can still finish before the blank page does
+        PBWebviewEvent::PageLoadFinish { url } if window_in_pool.blanking => {
+            if url == "about:blank" {
+                window_in_pool.blanking = false;
+                pool.finish_reset(hook.pool_item, &outgoing_tx, proxy);
+            }
+        }
+        PBWebviewEvent::PageLoadFailed { url, error } if window_in_pool.blanking => {
+            if url == "about:blank" {
+                eprintln!("Failed to blank webview {}: {error}", hook.pool_item);
+                window_in_pool.blanking = false;
+                pool.finish_reset(hook.pool_item, &outgoing_tx, proxy);
+            }
+        }
+        PBWebviewEvent::BlankTimedOut { reset_id } => {
+            if window_in_pool.blanking && window_in_pool.latest_reset == reset_id {
+                eprintln!("Timed out blanking webview {}", hook.pool_item);
+                window_in_pool.blanking = false;
+                pool.finish_reset(hook.pool_item, &outgoing_tx, proxy);
+            }
+        }
+        PBWebviewEvent::PageLoadStart { .. } if window_in_pool.blanking => {}
+        PBWebviewEvent::PageLoadStart { url } => {
+            window_in_pool.emit(&outgoing_tx, PageEvent::PageLoadStarted { url });
+
+            // Redirects and normalised URLs mean the started URL won't always match what was requested,
+            // so any load that starts is attributed to our most recent navigation.
+            window_in_pool.loading_navigation = Some(window_in_pool.latest_navigation);
+            window_in_pool.current_load = LoadDetails::default();
+
+            // Any condition check was running in the page we're leaving
+            if let Some(pending) = window_in_pool.pending_navigation.as_mut() {
+                pending.check = None;
+            }
+        }
+        PBWebviewEvent::ResponseReceived {
+            status, mime_type, ..
+        } => {
+            window_in_pool.current_load.status = status;
+            window_in_pool.current_load.mime_type = mime_type;
+        }
+        PBWebviewEvent::PageLoadFailed { error, .. } => {
+            window_in_pool.current_load.error = Some(error);
+        }
+        PBWebviewEvent::DomContentLoaded { url } => {
+            let Some(wait_until) = window_in_pool
+                .pending_for_current_load()
+                .map(|pending| pending.wait_until.clone())
+            else {
+                return;
+            };
+
+            match wait_until {
+                WaitUntil::DomContentLoaded => {
+                    complete_navigation(window_in_pool, url, &outgoing_tx);
+                }
+                WaitUntil::Selector(_) => {
+                    start_condition_check(window_in_pool, hook.pool_item, proxy);
+                }
+                WaitUntil::Load | WaitUntil::NetworkIdle(_) => {}
+            }
+        }
+        PBWebviewEvent::PageLoadFinish { url } => {
+            window_in_pool.emit(
+                &outgoing_tx,
+                PageEvent::PageLoadFinished { url: url.clone() },
+            );
+
+            let Some(pending) = window_in_pool.pending_for_current_load() else {
+                return;
+            };
+
+            // Pages that failed to load won't ever meet an in-page condition
+            let condition_met = match pending.wait_until {
+                WaitUntil::DomContentLoaded | WaitUntil::Load => true,
+                WaitUntil::NetworkIdle(_) | WaitUntil::Selector(_) => {
+                    window_in_pool.current_load.error.is_some()
+                }
+            };
+
+            if condition_met {
+                complete_navigation(window_in_pool, url, &outgoing_tx);
+            } else {
+                // Selector checks are usually already running from DOMContentLoaded
+                start_condition_check(window_in_pool, hook.pool_item, proxy);
+            }
+        }
+        PBWebviewEvent::Page(event) => {
+            // Nothing more will happen in a crashed page, so there's no point waiting on it
+            if let PageEvent::Crashed { reason } = &event {
+                if let Some(pending) = window_in_pool.pending_navigation.take() {
+                    respond_with_error(
+                        &outgoing_tx,
+                        pending.message_id,
+                        format!("The page crashed before its navigation completed: {reason}"),
+                    );
+                }
+            }
+
+            window_in_pool.emit(&outgoing_tx, event);
+        }
+        PBWebviewEvent::WaitConditionMet { check_id, url } => {
+            if window_in_pool.is_current_check(check_id) {
+                complete_navigation(window_in_pool, url, &outgoing_tx);
+            }
+        }
+        PBWebviewEvent::ResetComplete { error } => {
+            if let Some(error) = error {
+                eprintln!(
+                    "Failed to clear website data for webview {}: {error}",
+                    hook.pool_item
+                );
+            }
+
+            // Clearing website data also removed the storage state's cookies
+            if !pool.load_storage_state(hook.pool_item, proxy) {
+                pool.items[hook.pool_item].preparing = false;
+                pool.hand_out_window(hook.pool_item, &outgoing_tx);
+            }
+        }
+        PBWebviewEvent::StorageStateLoaded { error } => {
+            if let Some(error) = error {
+                eprintln!(
+                    "Failed to load storage state into webview {}: {error}",
+                    hook.pool_item
+                );
+            }
+
+            window_in_pool.preparing = false;
+            pool.hand_out_window(hook.pool_item, &outgoing_tx);
+        }
+        PBWebviewEvent::WaitConditionFailed { check_id, error } => {
+            if !window_in_pool.is_current_check(check_id) {
+                return;
+            }
+            let Some(pending) = window_in_pool.pending_navigation.take() else {
+                return;
+            };
+
+            outgoing_tx
+                .send(PBResponse {
+                    message_id: Some(pending.message_id),
+                    payload: PBResponsePayload::Error {
+                        original_message: None,
+                        message: format!(
+                            "Failed to wait for {:?} after navigating: {error}",
+                            pending.wait_until
+                        ),
+                    },
+                })
+                .expect("handle this error one day");
+        }
+    }
+}
+
+impl PoolItem {
+    /// The pending navigation, if the webview's current page load belongs to it
+    fn pending_for_current_load(&self) -> Option<&PendingNavigation> {
+        self.pending_navigation
+            .as_ref()
+            .filter(|pending| self.loading_navigation == Some(pending.navigation_id))
+    }
+
+    /// Unassigns this pool item so that it can be handed out again,
+    /// failing anything that was still waiting on it
+    fn recycle(&mut self, outgoing_tx: &Sender<PBResponse>) {
+        self.assigned_to = None;
+
+        if let Some(pending) = self.pending_navigation.take() {
+            outgoing_tx
+                .send(PBResponse {
+                    message_id: Some(pending.message_id),
+                    payload: PBResponsePayload::Error {
+                        original_message: None,
+                        message: "The window was released before its navigation completed".into(),
+                    },
+                })
+                .expect("handle this error one day");
+        }
+    }
+
+    /// Pushes an event to whoever the pool item is assigned to
+    fn emit(&self, outgoing_tx: &Sender<PBResponse>, event: PageEvent) {
+        // Nobody is listening to windows that aren't assigned
+        let Some(window_id) = self.assigned_to else {
+            return;
+        };
+
+        outgoing_tx
+            .send(PBResponse {
+                message_id: None,
+                payload: PBResponsePayload::PageEvent { window_id, event },
+            })
+            .expect("handle this error one day");
+    }
+
+    fn is_current_check(&self, check_id: u64) -> bool {
+        self.pending_navigation
+            .as_ref()
+            .is_some_and(|pending| pending.check == Some(check_id))
+    }
+}
+
+/// Responds to the pending navigation with what we know about the page load
+fn complete_navigation(
+    window_in_pool: &mut PoolItem,
+    url: String,
+    outgoing_tx: &Sender<PBResponse>,
+) {
+    let Some(pending) = window_in_pool.pending_navigation.take() else {
+        return;
+    };
+    let LoadDetails {
+        status,
+        mime_type,
+        error,
+    } = window_in_pool.current_load.clone();
+
+    outgoing_tx
+        .send(PBResponse {
+            message_id: Some(pending.message_id),
+            payload: PBResponsePayload::NavigationComplete(NavigationResult {
+                final_url: url,
+                status,
+                mime_type,
+                error,
+            }),
+        })
+        .expect("handle this error one day");
+}
+
+/// Runs the pending navigation's wait condition in the page,
+/// reporting back with a hook once it holds
+fn start_condition_check(
+    window_in_pool: &mut PoolItem,
+    pool_item: usize,
+    proxy: EventLoopProxy<Box<PBEvent>>,
+) {
+    let Some(pending) = window_in_pool.pending_navigation.as_mut() else {
+        return;
+    };
+    if pending.check.is_some() {
+        return;
+    }
+    let Some((script, arguments)) = condition_script(&pending.wait_until) else {
+        return;
+    };
+
+    window_in_pool.latest_check += 1;
+    let check_id = window_in_pool.latest_check;
+    pending.check = Some(check_id);
+
+    let check_callback = move |output: Result<String, ScriptException>| {
+        let event = match output.and_then(parse_script_output) {
+            Ok(output) => PBWebviewEvent::WaitConditionMet {
+                check_id,
+                url: serde_json::from_str(&output).unwrap_or_default(),
+            },
+            Err(e) => PBWebviewEvent::WaitConditionFailed {
+                check_id,
+                error: e.message,
+            },
+        };
+
+        _ = proxy.send_event(Box::new(PBEvent::Hook(PBHook { pool_item, event })));
+    };
+
+    platforms::Platform::run_js(
+        &window_in_pool.webview,
+        &wrap_script(script),
+        &arguments,
+        check_callback,
+    );
+}
+
+fn respond_with_error(
+    outgoing_tx: &Sender<PBResponse>,
+    message_id: u32,
+    message: impl std::fmt::Display,
+) {
+    outgoing_tx
+        .send(PBResponse {
+            message_id: Some(message_id),
+            payload: PBResponsePayload::Error {
+                original_message: None,
+                message: message.to_string(),
+            },
+        })
+        .expect("handle this error one day");
+}
+
+fn respond_with_completion(
+    outgoing_tx: &Sender<PBResponse>,
+    message_id: u32,
+    result: Result<(), String>,
+) {
+    match result {
+        Ok(()) => outgoing_tx
+            .send(PBResponse {
+                message_id: Some(message_id),
+                payload: PBResponsePayload::OperationComplete,
+            })
+            .expect("handle this error one day"),
+        Err(message) => respond_with_error(outgoing_tx, message_id, message),
+    }
+}
+
+fn handle_message(
+    msg: PBRequest,
+    pool: &mut Pool,
+    outgoing_tx: Sender<PBResponse>,
+    proxy: EventLoopProxy<Box<PBEvent>>,
+) {
+    let Some(message_id) = msg.message_id else {
+        outgoing_tx
+            .send(PBResponse {
+                message_id: None,
+                payload: PBResponsePayload::Error {
+                    original_message: serde_json::to_string(&msg).ok(),
+                    message: "Requests must have a message ID".into(),
+                },
+            })
+            .expect("Channel is open");
+        return;
+    };
+
+    match msg.payload {
+        PBRequestPayload::Tester(string) => {
+            outgoing_tx
+                .send(PBResponse {
+                    message_id: msg.message_id,
+                    payload: PBResponsePayload::Tester(format!("Responding to [{string}]")),
+                })
+                .expect("Sendable");
+        }
+        PBRequestPayload::Initialize(_) => {
+            outgoing_tx
+                .send(PBResponse {
+                    message_id: msg.message_id,
+                    payload: PBResponsePayload::Error {
+                        original_message: None,
+                        message: "Pagebrowse is already initialized".into(),
+                    },
+                })
+                .expect("Channel is open");
+        }
+        PBRequestPayload::Cancel {
+            message_id: cancelled_id,
+        } => {
+            // Answered so that whoever sent it can stop tracking it
+            if pool.waiting_for_windows.contains(&cancelled_id) {
+                pool.waiting_for_windows.retain(|id| *id != cancelled_id);
+                respond_with_error(&outgoing_tx, cancelled_id, "The request was cancelled");
+            }
+            for item in pool.items.iter_mut() {
+                if item
+                    .pending_navigation
+                    .as_ref()
+                    .is_some_and(|pending| pending.message_id == cancelled_id)
+                {
+                    item.pending_navigation = None;
+                }
+            }
+
+            outgoing_tx
+                .send(PBResponse {
+                    message_id: Some(message_id),
+                    payload: PBResponsePayload::OperationComplete,
+                })
+                .expect("handle this error one day");
+        }
+        PBRequestPayload::NewWindow => {
+            let Some((pool_index, item)) = pool
+                .items
+                .iter_mut()
+                .enumerate()
+                .find(|(_, item)| item.assigned_to.is_none() && !item.preparing)
+            else {
+                pool.waiting_for_windows.push_back(message_id);
+                return;
+            };
+
+            let assigned_to = pool.assign_window(pool_index);
+
+            outgoing_tx
+                .send(PBResponse {
+                    message_id: Some(message_id),
+                    payload: PBResponsePayload::NewWindowCreated { id: assigned_to },
+                })
+                .expect("handle this error one day");
+        }
+        PBRequestPayload::ReleaseWindow { window_id } => {
+            let window_in_pool = match pool.get_assigned_window(window_id) {
+                Ok(window_in_pool) => window_in_pool,
+                Err(e) => return respond_with_error(&outgoing_tx, message_id, e),
+            };
+
+            window_in_pool.recycle(&outgoing_tx);
+            let released_id = window_in_pool.id;
+
+            pool.release_assigned_window(window_id);
+
+            outgoing_tx
+                .send(PBResponse {
+                    message_id: Some(message_id),
+                    payload: PBResponsePayload::OperationComplete,
+                })
+                .expect("handle this error one day");
+
+            pool.reset_window(released_id, &outgoing_tx, proxy);
+        }
+        PBRequestPayload::Navigate {
+            window_id,
+            url,
+            wait_until,
+        } => {
+            let window_in_pool = match pool.get_assigned_window(window_id) {
+                Ok(window_in_pool) => window_in_pool,
+                Err(e) => return respond_with_error(&outgoing_tx, message_id, e),
+            };
+
+            if let Some(superseded) = window_in_pool.pending_navigation.take() {
+                outgoing_tx
+                    .send(PBResponse {
+                        message_id: Some(superseded.message_id),
+                        payload: PBResponsePayload::Error {
+                            original_message: None,
+                            message: format!("Navigation was superseded by a navigation to {url}"),
+                        },
+                    })
+                    .expect("handle this error one day");
+            }
+
+            window_in_pool.latest_navigation += 1;
+
+            let waiting = wait_until.is_some();
+            if let Some(wait_until) = wait_until {
+                window_in_pool.pending_navigation = Some(PendingNavigation {
+                    navigation_id: window_in_pool.latest_navigation,
+                    message_id,
+                    wait_until,
+                    check: None,
+                });
+            }
+
+            if let Err(e) = window_in_pool.webview.load_url(&url) {
+                window_in_pool.pending_navigation = None;
+                return respond_with_error(
+                    &outgoing_tx,
+                    message_id,
+                    format!("Failed to navigate to {url}: {e}"),
+                );
+            }
+
+            if !waiting {
+                outgoing_tx
+                    .send(PBResponse {
+                        message_id: Some(message_id),
+                        payload: PBResponsePayload::NavigationComplete(NavigationResult {
+                            final_url: url,
+                            status: None,
+                            mime_type: None,
+                            error: None,
+                        }),
+                    })
+                    .expect("handle this error one day");
+            }
+        }
+        PBRequestPayload::ResizeWindow {
+            window_id,
+            width,
+            height,
+        } => {
+            let window_in_pool = match pool.get_assigned_window(window_id) {
+                Ok(window_in_pool) => window_in_pool,
+                Err(e) => return respond_with_error(&outgoing_tx, message_id, e),
+            };
+
+            window_in_pool
+                .window
+                .set_inner_size(PhysicalSize::new(width as u32, height as u32));
+
+            let x = (window_in_pool.assigned_to.unwrap() % 4) * 1920 / 2;
+            let y = ((window_in_pool.assigned_to.unwrap() / 4) % 4) * 1080 / 2;
+
+            window_in_pool
+                .window
+                .set_outer_position(PhysicalPosition::new(x as u32, y as u32));
+
+            outgoing_tx
+                .send(PBResponse {
+                    message_id: Some(message_id),
+                    payload: PBResponsePayload::OperationComplete,
+                })
+                .expect("handle this error one day");
+        }
+        PBRequestPayload::EvaluateScript {
+            window_id,
+            script,
+            arguments,
+        } => {
+            let window_in_pool = match pool.get_assigned_window(window_id) {
+                Ok(window_in_pool) => window_in_pool,
+                Err(e) => return respond_with_error(&outgoing_tx, message_id, e),
+            };
+
+            if let Some(name) = arguments.keys().find(|name| !is_valid_argument_name(name)) {
+                outgoing_tx
+                    .send(PBResponse {
+                        message_id: Some(message_id),
+                        payload: PBResponsePayload::Error {
+                            original_message: None,
+                            message: format!(
+                                "Script argument `{name}` is not a valid JavaScript identifier"
+                            ),
+                        },
+                    })
+                    .expect("handle this error one day");
+                return;
+            }
+
+            let res_callback = move |output: Result<String, ScriptException>| {
+                let payload = match output.and_then(parse_script_output) {
+                    Ok(output) => PBResponsePayload::ScriptEvaluated { output },
+                    Err(ScriptException {
+                        message,
+                        line,
+                        column,
+                        stack,
+                    }) => PBResponsePayload::ScriptError {
+                        message,
+                        line,
+                        column,
+                        stack,
+                    },
+                };
+
+                outgoing_tx
+                    .send(PBResponse {
+                        message_id: Some(message_id),
+                        payload,
+                    })
+                    .expect("handle this error one day");
+            };
+
+            let js = wrap_script(&script);
+            platforms::Platform::run_js(&window_in_pool.webview, &js, &arguments, res_callback);
+        }
+        PBRequestPayload::GetCookies { window_id, url } => {
+            let window_in_pool = match pool.get_assigned_window(window_id) {
+                Ok(window_in_pool) => window_in_pool,
+                Err(e) => return respond_with_error(&outgoing_tx, message_id, e),
+            };
+
+            platforms::Platform::get_cookies(
+                &window_in_pool.webview,
+                url.as_deref(),
+                move |cookies| {
+                    let payload = match cookies {
+                        Ok(cookies) => PBResponsePayload::Cookies { cookies },
+                        Err(message) => PBResponsePayload::Error {
+                            original_message: None,
+                            message,
+                        },
+                    };
+
+                    outgoing_tx
+                        .send(PBResponse {
+                            message_id: Some(message_id),
+                            payload,
+                        })
+                        .expect("handle this error one day");
+                },
+            );
+        }
+        PBRequestPayload::SetCookies { window_id, cookies } => {
+            let window_in_pool = match pool.get_assigned_window(window_id) {
+                Ok(window_in_pool) => window_in_pool,
+                Err(e) => return respond_with_error(&outgoing_tx, message_id, e),
+            };
+
+            platforms::Platform::set_cookies(&window_in_pool.webview, cookies, move |res| {
+                respond_with_completion(&outgoing_tx, message_id, res)
+            });
+        }
+        PBRequestPayload::ClearCookies { window_id } => {
+            let window_in_pool = match pool.get_assigned_window(window_id) {
+                Ok(window_in_pool) => window_in_pool,
+                Err(e) => return respond_with_error(&outgoing_tx, message_id, e),
+            };
+
+            platforms::Platform::clear_cookies(&window_in_pool.webview, move |res| {
+                respond_with_completion(&outgoing_tx, message_id, res)
+            });
+        }
+        PBRequestPayload::Screenshot {
+            window_id,
+            path,
+            options,
+        } => {
+            let window_in_pool = match pool.get_assigned_window(window_id) {
+                Ok(window_in_pool) => window_in_pool,
+                Err(e) => return respond_with_error(&outgoing_tx, message_id, e),
+            };
+
+            let full_page = options.full_page;
+            let screenshot_callback = move |bytes: Result<&[u8], String>| {
+                let processed = bytes.and_then(|bytes| process_screenshot(bytes, &options));
+
+                let payload = match (processed, &path) {
+                    (Ok(bytes), Some(path)) => match std::fs::write(path, bytes) {
+                        Ok(()) => PBResponsePayload::OperationComplete,
+                        Err(e) => PBResponsePayload::Error {
+                            original_message: None,
+                            message: format!("Failed to write screenshot to {path}: {e}"),
+                        },
+                    },
+                    (Ok(bytes), None) => PBResponsePayload::ScreenshotCaptured { bytes },
+                    (Err(message), _) => PBResponsePayload::Error {
+                        original_message: None,
+                        message,
+                    },
+                };
+
+                outgoing_tx
+                    .send(PBResponse {
+                        message_id: Some(message_id),
+                        payload,
+                    })
+                    .expect("handle this error one day");
+            };
+
+            platforms::Platform::screenshot(
+                &window_in_pool.webview,
+                full_page,
+                screenshot_callback,
+            );
+        }
+    };
+}
@@ -0,0 +1,136 @@
+use std::io::Cursor;
+
+use image::{codecs::webp, DynamicImage, ImageOutputFormat};
+use pagebrowse_types::{ImageFormat, ScreenshotOptions};
+
+/// Takes the image bytes returned by a platform screenshot,
+/// and crops/transcodes them as requested by the consumer.
+pub fn process_screenshot(bytes: &[u8], options: &ScreenshotOptions) -> Result<Vec<u8>, String> {
+    let mut image = image::load_from_memory(bytes)
+        .map_err(|e| format!("Failed to decode the platform screenshot: {e}"))?;
+
+    if let Some(clip) = &options.clip {
+        if clip.width == 0
+            || clip.height == 0
+            || clip.x.saturating_add(clip.width) > image.width()
+            || clip.y.saturating_add(clip.height) > image.height()
+        {
+            return Err(format!(
+                "Clip region {}x{} at ({}, {}) is outside of the {}x{} screenshot",
+                clip.width,
+                clip.height,
+                clip.x,
+                clip.y,
+                image.width(),
+                image.height()
+            ));
+        }
+
+        image = image.crop_imm(clip.x, clip.y, clip.width, clip.height);
+    }
+
+    let mut output = Cursor::new(vec![]);
+    let encoded = match options.format {
+        ImageFormat::Png => image.write_to(&mut output, ImageOutputFormat::Png),
+        ImageFormat::Jpeg => {
+            // JPEG has no alpha channel
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_to(
+                &mut output,
+                ImageOutputFormat::Jpeg(options.quality.unwrap_or(80).min(100)),
+            )
+        }
+        ImageFormat::WebP => {
+            #[allow(deprecated)]
+            let quality = match options.quality {
+                Some(quality) => webp::WebPQuality::lossy(quality),
+                None => webp::WebPQuality::lossless(),
+            };
+            let image = image.to_rgba8();
+
+            #[allow(deprecated)]
+            webp::WebPEncoder::new_with_quality(&mut output, quality).encode(
+                &image,
+                image.width(),
+                image.height(),
+                image::ColorType::Rgba8,
+            )
+        }
+    };
+
+    encoded.map_err(|e| format!("Failed to encode screenshot: {e}"))?;
+
+    Ok(output.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat as DecodedFormat, Rgba, RgbaImage};
+    use pagebrowse_types::ClipRect;
+
+    fn platform_screenshot(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_fn(width, height, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+
+        let mut output = Cursor::new(vec![]);
+        DynamicImage::ImageRgba8(image)
+            .write_to(&mut output, ImageOutputFormat::Png)
+            .unwrap();
+        output.into_inner()
+    }
+
+    fn clip(x: u32, y: u32, width: u32, height: u32) -> ScreenshotOptions {
+        ScreenshotOptions {
+            clip: Some(ClipRect {
+                x,
+                y,
+                width,
+                height,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn screenshots_are_transcoded() {
+        let screenshot = platform_screenshot(4, 3);
+
+        for (format, decoded_format) in [
+            (ImageFormat::Png, DecodedFormat::Png),
+            (ImageFormat::Jpeg, DecodedFormat::Jpeg),
+            (ImageFormat::WebP, DecodedFormat::WebP),
+        ] {
+            let options = ScreenshotOptions {
+                format,
+                ..Default::default()
+            };
+            let output = process_screenshot(&screenshot, &options).unwrap();
+
+            assert_eq!(image::guess_format(&output).unwrap(), decoded_format);
+        }
+    }
+
+    #[test]
+    fn screenshots_are_clipped() {
+        let output = process_screenshot(&platform_screenshot(4, 3), &clip(1, 1, 3, 2)).unwrap();
+        let image = image::load_from_memory(&output).unwrap();
+
+        assert_eq!((image.width(), image.height()), (3, 2));
+        assert_eq!(image.to_rgba8().get_pixel(0, 0), &Rgba([1, 1, 0, 255]));
+    }
+
+    #[test]
+    fn clips_must_be_inside_the_screenshot() {
+        let screenshot = platform_screenshot(4, 3);
+
+        for options in [clip(0, 0, 0, 1), clip(3, 0, 2, 1), clip(0, 2, 1, 2)] {
+            assert!(process_screenshot(&screenshot, &options).is_err());
+        }
+    }
+
+    #[test]
+    fn undecodable_screenshots_are_an_error() {
+        let error = process_screenshot(b"not an image", &ScreenshotOptions::default()).unwrap_err();
+
+        assert!(error.starts_with("Failed to decode the platform screenshot"));
+    }
+}
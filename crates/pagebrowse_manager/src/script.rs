@@ -0,0 +1,181 @@
+use serde::Deserialize;
+
+/// A JavaScript exception thrown while evaluating a consumer script
+#[derive(Debug, Deserialize)]
+pub struct ScriptException {
+    pub message: String,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub stack: Option<String>,
+}
+
+impl ScriptException {
+    pub fn from_message(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            line: None,
+            column: None,
+            stack: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CaughtException {
+    #[serde(rename = "__pagebrowse_error")]
+    error: ScriptException,
+}
+
+/// Wraps a consumer script so that anything it throws is returned as a value
+/// we can recognise, rather than each platform reporting it in its own way.
+/// The script starts on the first line so that line numbers are unchanged.
+pub fn wrap_script(script: &str) -> String {
+    format!(
+        "try {{ {script}\n\
+         }} catch (e) {{\n\
+           return {{ \"__pagebrowse_error\": {{\n\
+             message: e instanceof Error ? `${{e.name}}: ${{e.message}}` : String(e),\n\
+             line: typeof e?.line === \"number\" ? e.line : null,\n\
+             column: typeof e?.column === \"number\" ? e.column : null,\n\
+             stack: typeof e?.stack === \"string\" ? e.stack : null,\n\
+           }} }};\n\
+         }}\n"
+    )
+}
+
+/// Words that can't be bound as variable names, including in strict mode and async functions
+const RESERVED_WORDS: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// Script arguments become local variables, so they need to be valid JavaScript identifiers
+pub fn is_valid_argument_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+
+    (first.is_ascii_alphabetic() || first == '_' || first == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !RESERVED_WORDS.contains(&name)
+}
+
+/// Splits the JSON output of a script wrapped by `wrap_script` into
+/// a successful result or the exception it caught.
+pub fn parse_script_output(output: String) -> Result<String, ScriptException> {
+    match serde_json::from_str::<CaughtException>(&output) {
+        Ok(CaughtException { error }) => Err(error),
+        Err(_) => Ok(output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrapped_scripts_start_on_the_first_line() {
+        let wrapped = wrap_script("return 1 + 1;");
+
+        assert!(wrapped.starts_with("try { return 1 + 1;\n"));
+        assert!(wrapped.contains("__pagebrowse_error"));
+    }
+
+    #[test]
+    fn script_output_is_passed_through() {
+        let output = parse_script_output(r#"{"value":1}"#.to_string()).unwrap();
+
+        assert_eq!(output, r#"{"value":1}"#);
+    }
+
+    #[test]
+    fn caught_exceptions_are_parsed() {
+        let output = r#"{"__pagebrowse_error":{"message":"TypeError: x is undefined","line":3,"column":7,"stack":null}}"#;
+        let exception = parse_script_output(output.to_string()).unwrap_err();
+
+        assert_eq!(exception.message, "TypeError: x is undefined");
+        assert_eq!(exception.line, Some(3));
+        assert_eq!(exception.column, Some(7));
+        assert_eq!(exception.stack, None);
+    }
+
+    #[test]
+    fn argument_names_must_be_identifiers() {
+        for name in [
+            "value",
+            "_private",
+            "$el",
+            "camelCase2",
+            "classes",
+            "letter",
+        ] {
+            assert!(is_valid_argument_name(name), "{name} should be valid");
+        }
+        for name in ["", "2fast", "with-dash", "has space", "é"] {
+            assert!(!is_valid_argument_name(name), "{name} should be invalid");
+        }
+    }
+
+    #[test]
+    fn argument_names_cant_be_reserved_words() {
+        for name in [
+            "class",
+            "this",
+            "arguments",
+            "eval",
+            "let",
+            "await",
+            "yield",
+            "null",
+        ] {
+            assert!(!is_valid_argument_name(name), "{name} should be invalid");
+        }
+    }
+}
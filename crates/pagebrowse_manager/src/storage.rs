@@ -0,0 +1,31 @@
+use std::collections::BTreeMap;
+
+use pagebrowse_types::OriginStorage;
+
+/// An init script that fills in localStorage the first time a window visits each origin.
+/// localStorage can only be written from a page on its origin, so this can't be done up front like cookies.
+pub fn restore_local_storage_script(origins: &[OriginStorage]) -> String {
+    let origins: BTreeMap<&str, &BTreeMap<String, String>> = origins
+        .iter()
+        .map(|origin| (origin.origin.as_str(), &origin.local_storage))
+        .collect();
+    let origins = serde_json::to_string(&origins).expect("Storage is serializable");
+
+    format!(
+        r#"(() => {{
+    const items = {origins}[location.origin];
+    if (!items) return;
+
+    // sessionStorage lasts as long as the window, so we only restore once rather than on every page load
+    try {{
+        if (sessionStorage.getItem("__pagebrowse_storage_restored")) return;
+        for (const [name, value] of Object.entries(items)) {{
+            localStorage.setItem(name, value);
+        }}
+        sessionStorage.setItem("__pagebrowse_storage_restored", "true");
+    }} catch (e) {{
+        console.warn("Pagebrowse failed to restore localStorage", e);
+    }}
+}})();"#
+    )
+}
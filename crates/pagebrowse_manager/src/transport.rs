@@ -0,0 +1,772 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use base64::{engine::general_purpose, Engine};
+use tao::event_loop::EventLoopProxy;
+
+use pagebrowse_types::{framing, Framing, InProcessConnection};
+
+use crate::{
+    InitializationParams, ManagerInfo, PBEvent, PBRequest, PBRequestPayload, PBResponse,
+    PBResponsePayload,
+};
+
+/// Where the manager takes requests from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenAddr {
+    /// A single consumer on stdin and stdout, which the manager exits along with
+    Stdio,
+    /// Any number of consumers connecting to a Unix socket at this path
+    Unix(PathBuf),
+    /// Any number of consumers connecting over TCP, e.g. to `127.0.0.1:9000`
+    Tcp(String),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(addr: &str) -> Result<Self, Self::Err> {
+        if addr == "stdio" {
+            return Ok(ListenAddr::Stdio);
+        }
+        if let Some(path) = addr.strip_prefix("unix:").filter(|path| !path.is_empty()) {
+            return Ok(ListenAddr::Unix(path.into()));
+        }
+        if let Some(host) = addr.strip_prefix("tcp:").filter(|host| !host.is_empty()) {
+            return Ok(ListenAddr::Tcp(host.into()));
+        }
+
+        Err(format!(
+            "Unsupported address `{addr}`, expected stdio, unix:<path> or tcp:<host>:<port>"
+        ))
+    }
+}
+
+impl std::fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddr::Stdio => write!(f, "stdio"),
+            ListenAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+            ListenAddr::Tcp(host) => write!(f, "tcp:{host}"),
+        }
+    }
+}
+
+/// Who is on the other end of a connection
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Consumer {
+    /// The process that started the manager, which the manager exits along with
+    Parent,
+    /// Anything that connects to the manager's socket
+    Socket,
+}
+
+/// Starts accepting consumers on `addr`, passing their requests to the router.
+/// TCP addresses that other hosts can reach are refused unless `allow_remote` is set.
+pub fn serve(addr: &ListenAddr, allow_remote: bool, router: Router) -> std::io::Result<()> {
+    match addr {
+        ListenAddr::Stdio => {
+            spawn_connection(
+                router,
+                std::io::stdin(),
+                std::io::stdout(),
+                Consumer::Parent,
+            );
+        }
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            use std::os::unix::fs::FileTypeExt;
+            use std::os::unix::net::{UnixListener, UnixStream};
+
+            // Clear out the socket left behind by an earlier manager, unless it's still running
+            if std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AddrInUse,
+                        format!(
+                            "Another manager is already listening on unix:{}",
+                            path.display()
+                        ),
+                    ));
+                }
+                std::fs::remove_file(path)?;
+            }
+
+            let listener = UnixListener::bind(path)?;
+            eprintln!("Pagebrowse is listening on unix:{}", path.display());
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+                        Ok((reader, writer)) => {
+                            spawn_connection(router.clone(), reader, writer, Consumer::Socket)
+                        }
+                        Err(e) => eprintln!("Failed to accept a connection: {e}"),
+                    }
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        ListenAddr::Unix(_) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "Unix sockets are not supported on this platform",
+            ));
+        }
+        ListenAddr::Tcp(host) => {
+            // Consumers can drive the pool's webviews, so by default only this host may connect
+            if !allow_remote {
+                if let Some(reachable) = host
+                    .to_socket_addrs()?
+                    .find(|addr| !addr.ip().is_loopback())
+                {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        format!(
+                            "Other hosts could connect to {reachable}, \
+                            listen on a loopback address or pass --allow-remote"
+                        ),
+                    ));
+                }
+            }
+
+            let listener = std::net::TcpListener::bind(host)?;
+            eprintln!("Pagebrowse is listening on tcp:{}", listener.local_addr()?);
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    match stream.and_then(|stream| Ok((stream.try_clone()?, stream))) {
+                        Ok((reader, writer)) => {
+                            spawn_connection(router.clone(), reader, writer, Consumer::Socket)
+                        }
+                        Err(e) => eprintln!("Failed to accept a connection: {e}"),
+                    }
+                }
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Connects a consumer in the same process, which passes messages over channels
+/// rather than framing them onto a stream
+pub fn connect_in_process(router: Router) -> InProcessConnection {
+    let (request_tx, request_rx) = std::sync::mpsc::channel::<PBRequest>();
+    let (outgoing_tx, outgoing_rx) = std::sync::mpsc::channel::<PBResponse>();
+
+    std::thread::spawn(move || {
+        let connection = router.connect(outgoing_tx.clone(), true);
+        let mut initialized = false;
+
+        // Ends once the consumer drops its sender
+        for msg in request_rx {
+            if initialized {
+                router.request(connection, msg, &outgoing_tx);
+            } else {
+                initialized = router.initialize(msg, &outgoing_tx).is_some();
+            }
+        }
+
+        router.disconnect(connection);
+    });
+
+    InProcessConnection {
+        requests: request_tx,
+        responses: outgoing_rx,
+    }
+}
+
+/// Reads requests from a consumer until it disconnects, and writes back whatever the router sends it
+fn spawn_connection(
+    router: Router,
+    reader: impl Read + Send + 'static,
+    mut writer: impl Write + Send + 'static,
+    consumer: Consumer,
+) {
+    let (outgoing_tx, outgoing_rx) = std::sync::mpsc::channel::<PBResponse>();
+
+    std::thread::spawn(move || {
+        let mut framing = Framing::Base64;
+
+        for mut msg in outgoing_rx {
+            let frame = match framing {
+                Framing::Base64 => framing::encode_base64(&serde_json::to_vec(&msg).unwrap()),
+                Framing::LengthPrefixed => {
+                    let attachments = msg.take_attachments();
+                    framing::encode_length_prefixed(
+                        &serde_json::to_vec(&msg).unwrap(),
+                        &attachments,
+                    )
+                }
+            };
+
+            let written = writer.write_all(&frame).and_then(|_| writer.flush());
+            if written.is_err() {
+                // The reader will notice the consumer has gone
+                break;
+            }
+
+            // The handshake response is the last message in the old framing
+            if let PBResponsePayload::Initialized {
+                framing: agreed, ..
+            } = msg.payload
+            {
+                framing = agreed;
+            }
+        }
+    });
+
+    std::thread::spawn(move || {
+        let connection = router.connect(outgoing_tx.clone(), consumer == Consumer::Parent);
+        let mut reader = BufReader::new(reader);
+        let mut framing = None;
+
+        loop {
+            let frame = match framing {
+                None | Some(Framing::Base64) => read_base64_frame(&mut reader),
+                Some(Framing::LengthPrefixed) => read_length_prefixed_frame(&mut reader),
+            };
+
+            let json = match frame {
+                Ok(Some(json)) => json,
+                // EOF Reached
+                Ok(None) => break,
+                Err(FrameError::Invalid(message)) => {
+                    write_error(&outgoing_tx, message);
+                    continue;
+                }
+                Err(FrameError::Broken(message)) => {
+                    write_error(&outgoing_tx, message);
+                    break;
+                }
+            };
+
+            let Ok(msg) = parse_json_or_write_error(json, &outgoing_tx) else {
+                continue;
+            };
+
+            if framing.is_some() {
+                router.request(connection, msg, &outgoing_tx);
+            } else {
+                framing = router.initialize(msg, &outgoing_tx);
+            }
+        }
+
+        if consumer == Consumer::Parent {
+            std::process::exit(0);
+        }
+        router.disconnect(connection);
+    });
+}
+
+enum FrameError {
+    /// The frame couldn't be read, but the next one can be
+    Invalid(String),
+    /// We've lost track of where frames start, so nothing more can be read
+    Broken(String),
+}
+
+/// Returns the JSON of the next frame, or `None` once the consumer has disconnected
+fn read_base64_frame(reader: &mut impl BufRead) -> Result<Option<Vec<u8>>, FrameError> {
+    let mut buf = vec![];
+    let read = reader.read_until(b',', &mut buf);
+    if read.is_err() || buf.pop().is_none() {
+        return Ok(None);
+    }
+
+    general_purpose::STANDARD
+        .decode(buf)
+        .map(Some)
+        .map_err(|_| FrameError::Invalid("Unparseable message, not valid base64".into()))
+}
+
+fn read_length_prefixed_frame(reader: &mut impl Read) -> Result<Option<Vec<u8>>, FrameError> {
+    let mut header = [0; framing::HEADER_LEN];
+    // Only running out before the first byte is a clean disconnect
+    loop {
+        match reader.read(&mut header[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(FrameError::Broken(format!("Failed to read a frame: {e}"))),
+        }
+    }
+    reader
+        .read_exact(&mut header[1..])
+        .map_err(|e| FrameError::Broken(format!("Failed to read a frame: {e}")))?;
+    let (json_len, attachments) = framing::parse_header(header).map_err(FrameError::Broken)?;
+
+    let mut json = vec![0; json_len];
+    reader
+        .read_exact(&mut json)
+        .map_err(|e| FrameError::Broken(format!("Failed to read a frame: {e}")))?;
+
+    // Requests don't carry binary data, but the attachments still need reading past
+    for _ in 0..attachments {
+        let mut prefix = [0; 4];
+        reader
+            .read_exact(&mut prefix)
+            .map_err(|e| FrameError::Broken(format!("Failed to read a frame: {e}")))?;
+        let len = framing::parse_length(prefix).map_err(FrameError::Broken)?;
+        std::io::copy(&mut reader.take(len as u64), &mut std::io::sink())
+            .map_err(|e| FrameError::Broken(format!("Failed to read a frame: {e}")))?;
+    }
+    if attachments > 0 {
+        return Err(FrameError::Invalid(
+            "Requests can't carry attachments".into(),
+        ));
+    }
+
+    Ok(Some(json))
+}
+
+fn write_error(outgoing_tx: &Sender<PBResponse>, message: String) {
+    _ = outgoing_tx.send(PBResponse {
+        message_id: None,
+        payload: PBResponsePayload::Error {
+            original_message: None,
+            message,
+        },
+    });
+}
+
+fn parse_json_or_write_error(
+    decoded: Vec<u8>,
+    outgoing_tx: &Sender<PBResponse>,
+) -> Result<PBRequest, ()> {
+    match serde_json::from_slice::<PBRequest>(&decoded) {
+        Ok(msg) => Ok(msg),
+        Err(e) => {
+            let error = match std::str::from_utf8(&decoded[..]) {
+                Ok(msg) => PBResponsePayload::Error {
+                    original_message: Some(msg.to_string()),
+                    message: format!("{e}"),
+                },
+                Err(_) => PBResponsePayload::Error {
+                    original_message: None,
+                    message:
+                        "Pagebrowse was unable to parse the message it was provided via the service"
+                            .to_string(),
+                },
+            };
+
+            _ = outgoing_tx.send(PBResponse {
+                message_id: None,
+                payload: error,
+            });
+
+            Err(())
+        }
+    }
+}
+
+/// The window a request acts on, if it acts on one
+fn target_window(payload: &PBRequestPayload) -> Option<u32> {
+    match payload {
+        PBRequestPayload::ReleaseWindow { window_id }
+        | PBRequestPayload::Navigate { window_id, .. }
+        | PBRequestPayload::ResizeWindow { window_id, .. }
+        | PBRequestPayload::EvaluateScript { window_id, .. }
+        | PBRequestPayload::GetCookies { window_id, .. }
+        | PBRequestPayload::SetCookies { window_id, .. }
+        | PBRequestPayload::ClearCookies { window_id }
+        | PBRequestPayload::Screenshot { window_id, .. } => Some(*window_id),
+        PBRequestPayload::Tester(_)
+        | PBRequestPayload::Initialize(_)
+        | PBRequestPayload::NewWindow
+        | PBRequestPayload::Cancel { .. } => None,
+    }
+}
+
+/// A request from a consumer that hasn't been answered yet
+struct InFlight {
+    connection: u32,
+    /// The ID the consumer gave the request
+    message_id: u32,
+    new_window: bool,
+    /// The consumer has stopped waiting, so the response is dropped,
+    /// and any window handed out for it is released
+    cancelled: bool,
+}
+
+struct RouterState {
+    proxy: EventLoopProxy<Box<PBEvent>>,
+    /// Requests wait here until the event loop has started
+    queued: Option<Vec<PBRequest>>,
+    /// Taken by the first consumer to initialize the manager
+    init_tx: Option<Sender<InitializationParams>>,
+    /// Sent to each consumer as it initializes
+    info: ManagerInfo,
+    connections: HashMap<u32, Sender<PBResponse>>,
+    /// Connections from this process or its parent, which the manager may write files for.
+    /// Socket consumers could be anyone, so they only get data sent back to them.
+    local: HashSet<u32>,
+    next_connection: u32,
+    next_message_id: u32,
+    in_flight: HashMap<u32, InFlight>,
+    /// Which connection each assigned window belongs to
+    windows: HashMap<u32, u32>,
+}
+
+impl RouterState {
+    fn dispatch(&mut self, request: PBRequest) {
+        match &mut self.queued {
+            Some(queued) => queued.push(request),
+            None => {
+                _ = self.proxy.send_event(Box::new(PBEvent::Request(request)));
+            }
+        }
+    }
+
+    /// Sends a request on behalf of the manager itself, ignoring its response
+    fn dispatch_internal(&mut self, payload: PBRequestPayload) {
+        let message_id = self.next_message_id;
+        self.next_message_id = self.next_message_id.wrapping_add(1);
+
+        self.dispatch(PBRequest {
+            message_id: Some(message_id),
+            payload,
+        });
+    }
+}
+
+/// Shares one pool between every connected consumer.
+/// Each consumer numbers its own requests, so the router gives every request
+/// a manager-wide message ID, and maps responses and events back to their consumer.
+#[derive(Clone)]
+pub struct Router {
+    state: Arc<Mutex<RouterState>>,
+}
+
+impl Router {
+    /// Returns the router, and a receiver for the parameters of the first `Initialize` request
+    pub fn new(
+        proxy: EventLoopProxy<Box<PBEvent>>,
+        info: ManagerInfo,
+    ) -> (Self, Receiver<InitializationParams>) {
+        let (init_tx, init_rx) = std::sync::mpsc::channel();
+
+        let router = Self {
+            state: Arc::new(Mutex::new(RouterState {
+                proxy,
+                queued: Some(vec![]),
+                init_tx: Some(init_tx),
+                info,
+                connections: HashMap::new(),
+                local: HashSet::new(),
+                next_connection: 0,
+                next_message_id: 0,
+                in_flight: HashMap::new(),
+                windows: HashMap::new(),
+            })),
+        };
+
+        (router, init_rx)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RouterState> {
+        self.state.lock().expect("Router lock is not poisoned")
+    }
+
+    /// Passes any queued requests on to the event loop, and everything after them as it arrives
+    pub fn start(&self) {
+        let mut state = self.lock();
+        for request in state.queued.take().unwrap_or_default() {
+            state.dispatch(request);
+        }
+    }
+
+    /// Sends the pool's responses and events to the consumers they belong to
+    pub fn route_responses(&self, outgoing_rx: Receiver<PBResponse>) {
+        let router = self.clone();
+        std::thread::spawn(move || {
+            for response in outgoing_rx {
+                router.respond(response);
+            }
+        });
+    }
+
+    fn connect(&self, outgoing_tx: Sender<PBResponse>, local: bool) -> u32 {
+        let mut state = self.lock();
+        let connection = state.next_connection;
+        state.next_connection += 1;
+        state.connections.insert(connection, outgoing_tx);
+        if local {
+            state.local.insert(connection);
+        }
+        connection
+    }
+
+    /// Handles the requests a consumer sends before it has initialized,
+    /// returning the framing for the rest of the connection once it has
+    fn initialize(&self, msg: PBRequest, outgoing_tx: &Sender<PBResponse>) -> Option<Framing> {
+        let PBRequestPayload::Initialize(params) = msg.payload else {
+            _ = outgoing_tx.send(PBResponse {
+                message_id: msg.message_id,
+                payload: PBResponsePayload::Error {
+                    original_message: None,
+                    message: "Initialize message has not been sent, Pagebrowse is not yet ready"
+                        .into(),
+                },
+            });
+            return None;
+        };
+
+        let framing = params.framing;
+        let mut state = self.lock();
+
+        // The consumer is left to decide whether it can carry on
+        if let Some(version) = params.protocol_version {
+            if version != state.info.protocol_version {
+                eprintln!(
+                    "A consumer speaking protocol version {version} connected, but this manager speaks version {}",
+                    state.info.protocol_version
+                );
+            }
+        }
+
+        // The pool is built from the first consumer's parameters,
+        // and later consumers share it as it is
+        if let Some(init_tx) = state.init_tx.take() {
+            _ = init_tx.send(params);
+        }
+
+        _ = outgoing_tx.send(PBResponse {
+            message_id: msg.message_id,
+            payload: PBResponsePayload::Initialized {
+                framing,
+                manager: state.info.clone(),
+            },
+        });
+        Some(framing)
+    }
+
+    fn request(&self, connection: u32, msg: PBRequest, outgoing_tx: &Sender<PBResponse>) {
+        let Some(message_id) = msg.message_id else {
+            _ = outgoing_tx.send(PBResponse {
+                message_id: None,
+                payload: PBResponsePayload::Error {
+                    original_message: serde_json::to_string(&msg).ok(),
+                    message: "Requests must have a message ID".into(),
+                },
+            });
+            return;
+        };
+
+        let mut state = self.lock();
+
+        // Other consumers' windows are treated as if they don't exist
+        if let Some(window_id) = target_window(&msg.payload) {
+            if state.windows.get(&window_id) != Some(&connection) {
+                _ = outgoing_tx.send(PBResponse {
+                    message_id: Some(message_id),
+                    payload: PBResponsePayload::Error {
+                        original_message: None,
+                        message: format!(
+                            "Window {window_id} does not exist, or has already been released"
+                        ),
+                    },
+                });
+                return;
+            }
+        }
+
+        if let PBRequestPayload::Screenshot { path: Some(_), .. } = &msg.payload {
+            if !state.local.contains(&connection) {
+                _ = outgoing_tx.send(PBResponse {
+                    message_id: Some(message_id),
+                    payload: PBResponsePayload::Error {
+                        original_message: None,
+                        message: "Screenshots can't be saved to a path over a socket, \
+                            leave the path out to have the screenshot sent back"
+                            .into(),
+                    },
+                });
+                return;
+            }
+        }
+
+        let payload = match msg.payload {
+            PBRequestPayload::Cancel {
+                message_id: cancelled_id,
+            } => {
+                let cancelled = state.in_flight.iter().find_map(|(id, in_flight)| {
+                    (in_flight.connection == connection && in_flight.message_id == cancelled_id)
+                        .then_some(*id)
+                });
+                let Some(cancelled) = cancelled else {
+                    // Already answered, so there's nothing to cancel
+                    _ = outgoing_tx.send(PBResponse {
+                        message_id: Some(message_id),
+                        payload: PBResponsePayload::OperationComplete,
+                    });
+                    return;
+                };
+
+                // A window can still be handed out before the cancellation lands, and needs releasing
+                match state.in_flight.get_mut(&cancelled) {
+                    Some(in_flight) if in_flight.new_window => in_flight.cancelled = true,
+                    _ => {
+                        state.in_flight.remove(&cancelled);
+                    }
+                }
+                PBRequestPayload::Cancel {
+                    message_id: cancelled,
+                }
+            }
+            PBRequestPayload::ReleaseWindow { window_id } => {
+                state.windows.remove(&window_id);
+                PBRequestPayload::ReleaseWindow { window_id }
+            }
+            payload => payload,
+        };
+
+        let routed_id = state.next_message_id;
+        state.next_message_id = state.next_message_id.wrapping_add(1);
+        state.in_flight.insert(
+            routed_id,
+            InFlight {
+                connection,
+                message_id,
+                new_window: matches!(payload, PBRequestPayload::NewWindow),
+                cancelled: false,
+            },
+        );
+
+        state.dispatch(PBRequest {
+            message_id: Some(routed_id),
+            payload,
+        });
+    }
+
+    fn respond(&self, response: PBResponse) {
+        let mut state = self.lock();
+
+        let Some(routed_id) = response.message_id else {
+            if let PBResponsePayload::PageEvent { window_id, .. } = &response.payload {
+                let outgoing_tx = state
+                    .windows
+                    .get(window_id)
+                    .and_then(|connection| state.connections.get(connection));
+                if let Some(outgoing_tx) = outgoing_tx {
+                    _ = outgoing_tx.send(response);
+                }
+            }
+            return;
+        };
+
+        let Some(in_flight) = state.in_flight.remove(&routed_id) else {
+            // A response to the manager's own request, or to one that was cancelled
+            return;
+        };
+
+        let outgoing_tx = state.connections.get(&in_flight.connection).cloned();
+        let Some(outgoing_tx) = outgoing_tx.filter(|_| !in_flight.cancelled) else {
+            // Nobody is waiting for this window any more
+            if let PBResponsePayload::NewWindowCreated { id } = response.payload {
+                state.dispatch_internal(PBRequestPayload::ReleaseWindow { window_id: id });
+            }
+            return;
+        };
+
+        if let PBResponsePayload::NewWindowCreated { id } = &response.payload {
+            state.windows.insert(*id, in_flight.connection);
+        }
+
+        _ = outgoing_tx.send(PBResponse {
+            message_id: Some(in_flight.message_id),
+            payload: response.payload,
+        });
+    }
+
+    /// Releases everything a consumer was using once it has gone
+    fn disconnect(&self, connection: u32) {
+        let mut state = self.lock();
+        state.connections.remove(&connection);
+        state.local.remove(&connection);
+
+        let windows: Vec<u32> = state
+            .windows
+            .iter()
+            .filter(|(_, owner)| **owner == connection)
+            .map(|(window_id, _)| *window_id)
+            .collect();
+        for window_id in windows {
+            state.windows.remove(&window_id);
+            state.dispatch_internal(PBRequestPayload::ReleaseWindow { window_id });
+        }
+
+        let abandoned: Vec<u32> = state
+            .in_flight
+            .iter()
+            .filter(|(_, in_flight)| in_flight.connection == connection)
+            .map(|(id, _)| *id)
+            .collect();
+        for routed_id in abandoned {
+            // Windows that are handed out before the cancellation lands still need releasing
+            match state.in_flight.get_mut(&routed_id) {
+                Some(in_flight) if in_flight.new_window => in_flight.cancelled = true,
+                _ => {
+                    state.in_flight.remove(&routed_id);
+                }
+            }
+            state.dispatch_internal(PBRequestPayload::Cancel {
+                message_id: routed_id,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_addresses_are_parsed() {
+        assert_eq!("stdio".parse(), Ok(ListenAddr::Stdio));
+        assert_eq!(
+            "unix:/tmp/pagebrowse.sock".parse(),
+            Ok(ListenAddr::Unix("/tmp/pagebrowse.sock".into()))
+        );
+        assert_eq!(
+            "tcp:127.0.0.1:9000".parse(),
+            Ok(ListenAddr::Tcp("127.0.0.1:9000".into()))
+        );
+
+        for addr in ["", "unix:", "tcp:", "127.0.0.1:9000", "http://localhost"] {
+            assert!(addr.parse::<ListenAddr>().is_err(), "Parsed `{addr}`");
+        }
+    }
+
+    #[test]
+    fn listen_addresses_display_as_they_are_parsed() {
+        for addr in ["stdio", "unix:/tmp/pagebrowse.sock", "tcp:127.0.0.1:9000"] {
+            assert_eq!(addr.parse::<ListenAddr>().unwrap().to_string(), addr);
+        }
+    }
+
+    #[test]
+    fn length_prefixed_frames_end_cleanly_only_between_frames() {
+        let json = br#"{"message_id":0,"payload":"NewWindow"}"#;
+        let mut frame = framing::encode_length_prefixed(json, &[]);
+
+        let mut reader = &frame[..];
+        assert!(matches!(
+            read_length_prefixed_frame(&mut reader),
+            Ok(Some(read)) if read == json
+        ));
+        assert!(matches!(read_length_prefixed_frame(&mut reader), Ok(None)));
+
+        for len in [framing::HEADER_LEN + 4, 2] {
+            frame.truncate(len);
+            assert!(matches!(
+                read_length_prefixed_frame(&mut &frame[..]),
+                Err(FrameError::Broken(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,629 @@
+//! Throws misbehaving consumer traffic at a real manager process,
+//! checking that each bad message is answered with an error and that the manager keeps serving.
+//!
+//! The manager opens real webviews, so these tests need a display (e.g. run under `xvfb-run`).
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use base64::{engine::general_purpose, Engine};
+use pagebrowse_types::{
+    framing, ConsoleLevel, Framing, InitializationParams, PBRequest, PBRequestPayload, PBResponse,
+    PBResponsePayload, PageEvent, WaitUntil, WindowReset, PROTOCOL_VERSION,
+};
+
+struct Manager {
+    /// Unset when connected to a manager that something else is running
+    child: Option<Child>,
+    stdin: Box<dyn Write>,
+    stdout: BufReader<Box<dyn Read>>,
+    next_message_id: u32,
+    framing: Framing,
+    /// Page events read while looking for a response
+    events: VecDeque<(u32, PageEvent)>,
+}
+
+impl Manager {
+    fn start() -> Self {
+        Self::start_with(Framing::Base64)
+    }
+
+    fn start_with(framing: Framing) -> Self {
+        let mut manager = Self::spawn();
+        manager.initialize(framing);
+        manager
+    }
+
+    /// Starts a manager without initializing it
+    fn spawn() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_pagebrowse_manager"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("Manager should start");
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        Self::new(Some(child), Box::new(stdin), Box::new(stdout))
+    }
+
+    /// Connects to a manager listening on a Unix socket
+    fn connect(socket: &Path) -> Self {
+        let stream = UnixStream::connect(socket).expect("Manager should be listening");
+        let reader = stream.try_clone().unwrap();
+        let mut manager = Self::new(None, Box::new(stream), Box::new(reader));
+        manager.initialize(Framing::Base64);
+        manager
+    }
+
+    fn new(child: Option<Child>, stdin: Box<dyn Write>, stdout: Box<dyn Read>) -> Self {
+        Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+            next_message_id: 0,
+            framing: Framing::Base64,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn initialize_request(framing: Framing) -> PBRequestPayload {
+        PBRequestPayload::Initialize(InitializationParams {
+            pool_size: 2,
+            visible: false,
+            init_script: None,
+            window_reset: WindowReset::Soft,
+            isolated_sessions: false,
+            storage_state: None,
+            framing,
+            protocol_version: Some(PROTOCOL_VERSION),
+        })
+    }
+
+    fn initialize(&mut self, framing: Framing) {
+        let response = self.request(Self::initialize_request(framing));
+        match response {
+            PBResponsePayload::Initialized {
+                framing: agreed,
+                manager: info,
+            } => {
+                assert_eq!(agreed, framing);
+                assert_eq!(info.protocol_version, PROTOCOL_VERSION);
+                assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+            }
+            other => panic!("Expected the manager to initialize, got {other:?}"),
+        }
+        self.framing = framing;
+    }
+
+    /// Sends a base64 frame, without checking that it is valid base64
+    fn send_frame(&mut self, frame: &[u8]) {
+        self.stdin.write_all(frame).unwrap();
+        self.stdin.write_all(b",").unwrap();
+        self.stdin.flush().unwrap();
+    }
+
+    fn send_json(&mut self, json: &str) {
+        match self.framing {
+            Framing::Base64 => self.send_frame(general_purpose::STANDARD.encode(json).as_bytes()),
+            Framing::LengthPrefixed => {
+                let frame = framing::encode_length_prefixed(json.as_bytes(), &[]);
+                self.stdin.write_all(&frame).unwrap();
+                self.stdin.flush().unwrap();
+            }
+        }
+    }
+
+    fn read_frame(&mut self) -> PBResponse {
+        match self.framing {
+            Framing::Base64 => {
+                let mut buf = vec![];
+                self.stdout.read_until(b',', &mut buf).unwrap();
+                assert_eq!(buf.pop(), Some(b','), "Manager closed its stdout");
+
+                let decoded = general_purpose::STANDARD.decode(buf).unwrap();
+                serde_json::from_slice(&decoded).unwrap()
+            }
+            Framing::LengthPrefixed => {
+                let mut header = [0; framing::HEADER_LEN];
+                self.stdout.read_exact(&mut header).unwrap();
+                let (json_len, attachment_count) = framing::parse_header(header).unwrap();
+
+                let mut json = vec![0; json_len];
+                self.stdout.read_exact(&mut json).unwrap();
+                let mut response: PBResponse = serde_json::from_slice(&json).unwrap();
+
+                let mut attachments = vec![];
+                for _ in 0..attachment_count {
+                    let mut prefix = [0; 4];
+                    self.stdout.read_exact(&mut prefix).unwrap();
+                    let mut attachment = vec![0; framing::parse_length(prefix).unwrap()];
+                    self.stdout.read_exact(&mut attachment).unwrap();
+                    attachments.push(attachment);
+                }
+                response.restore_attachments(attachments).unwrap();
+
+                response
+            }
+        }
+    }
+
+    /// Reads the next response, setting aside any page events that arrive first
+    fn read_response(&mut self) -> PBResponse {
+        loop {
+            let response = self.read_frame();
+            match response.payload {
+                PBResponsePayload::PageEvent { window_id, event } => {
+                    self.events.push_back((window_id, event))
+                }
+                _ => return response,
+            }
+        }
+    }
+
+    fn next_event(&mut self) -> (u32, PageEvent) {
+        if let Some(event) = self.events.pop_front() {
+            return event;
+        }
+
+        match self.read_frame() {
+            PBResponse {
+                message_id: None,
+                payload: PBResponsePayload::PageEvent { window_id, event },
+            } => (window_id, event),
+            other => panic!("Expected a page event, got {other:?}"),
+        }
+    }
+
+    fn send_request(&mut self, payload: PBRequestPayload) -> u32 {
+        let message_id = self.next_message_id;
+        self.next_message_id += 1;
+
+        let request = PBRequest {
+            message_id: Some(message_id),
+            payload,
+        };
+        self.send_json(&serde_json::to_string(&request).unwrap());
+
+        message_id
+    }
+
+    fn request(&mut self, payload: PBRequestPayload) -> PBResponsePayload {
+        let message_id = self.send_request(payload);
+
+        let response = self.read_response();
+        assert_eq!(response.message_id, Some(message_id));
+        response.payload
+    }
+
+    fn new_window(&mut self) -> u32 {
+        match self.request(PBRequestPayload::NewWindow) {
+            PBResponsePayload::NewWindowCreated { id } => id,
+            other => panic!("Expected a new window, got {other:?}"),
+        }
+    }
+
+    /// Checks that the manager is still alive and answering requests
+    fn assert_responsive(&mut self) {
+        let response = self.request(PBRequestPayload::Tester("ping".into()));
+        assert!(matches!(response, PBResponsePayload::Tester(_)));
+    }
+}
+
+impl Drop for Manager {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            _ = child.kill();
+            _ = child.wait();
+        }
+    }
+}
+
+/// A manager listening on a Unix socket, for consumers to `Manager::connect` to
+struct SocketManager {
+    child: Child,
+    socket: PathBuf,
+}
+
+impl SocketManager {
+    fn start() -> Self {
+        // Tests run in parallel, and each needs a manager of its own
+        static NEXT_SOCKET: AtomicU32 = AtomicU32::new(0);
+        let socket = std::env::temp_dir().join(format!(
+            "pagebrowse-robustness-{}-{}.sock",
+            std::process::id(),
+            NEXT_SOCKET.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let child = Command::new(env!("CARGO_BIN_EXE_pagebrowse_manager"))
+            .arg("--listen")
+            .arg(format!("unix:{}", socket.display()))
+            .spawn()
+            .expect("Manager should start");
+
+        let started = Instant::now();
+        while UnixStream::connect(&socket).is_err() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "Manager didn't start listening"
+            );
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        Self { child, socket }
+    }
+}
+
+impl Drop for SocketManager {
+    fn drop(&mut self) {
+        _ = self.child.kill();
+        _ = self.child.wait();
+        _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+fn assert_error(payload: PBResponsePayload) -> String {
+    match payload {
+        PBResponsePayload::Error { message, .. } => message,
+        other => panic!("Expected an error, got {other:?}"),
+    }
+}
+
+#[test]
+fn unknown_window_ids_are_errors() {
+    let mut manager = Manager::start();
+
+    let requests = [
+        PBRequestPayload::Navigate {
+            window_id: 42,
+            url: "about:blank".into(),
+            wait_until: None,
+        },
+        PBRequestPayload::ResizeWindow {
+            window_id: 42,
+            width: 800,
+            height: 600,
+        },
+        PBRequestPayload::EvaluateScript {
+            window_id: 42,
+            script: "return 1;".into(),
+            arguments: Default::default(),
+        },
+        PBRequestPayload::Screenshot {
+            window_id: 42,
+            path: None,
+            options: Default::default(),
+        },
+        PBRequestPayload::ReleaseWindow { window_id: 42 },
+    ];
+
+    for request in requests {
+        let message = assert_error(manager.request(request));
+        assert!(message.contains("Window 42"), "Unexpected error: {message}");
+        manager.assert_responsive();
+    }
+}
+
+#[test]
+fn double_release_is_an_error() {
+    let mut manager = Manager::start();
+    let window_id = manager.new_window();
+
+    let response = manager.request(PBRequestPayload::ReleaseWindow { window_id });
+    assert!(matches!(response, PBResponsePayload::OperationComplete));
+
+    assert_error(manager.request(PBRequestPayload::ReleaseWindow { window_id }));
+    manager.assert_responsive();
+}
+
+#[test]
+fn released_windows_are_unusable() {
+    let mut manager = Manager::start();
+    let window_id = manager.new_window();
+    let other_window_id = manager.new_window();
+
+    manager.request(PBRequestPayload::ReleaseWindow { window_id });
+
+    assert_error(manager.request(PBRequestPayload::Navigate {
+        window_id,
+        url: "about:blank".into(),
+        wait_until: None,
+    }));
+
+    // Other windows carry on as normal
+    let response = manager.request(PBRequestPayload::EvaluateScript {
+        window_id: other_window_id,
+        script: "return 1 + 1;".into(),
+        arguments: Default::default(),
+    });
+    match response {
+        PBResponsePayload::ScriptEvaluated { output } => assert_eq!(output, "2"),
+        other => panic!("Expected script output, got {other:?}"),
+    }
+}
+
+#[test]
+fn releasing_fails_pending_navigations() {
+    let mut manager = Manager::start();
+    let window_id = manager.new_window();
+
+    let navigation_id = manager.send_request(PBRequestPayload::Navigate {
+        window_id,
+        url: "about:blank".into(),
+        wait_until: Some(WaitUntil::Selector("#never-going-to-exist".into())),
+    });
+    let release_id = manager.send_request(PBRequestPayload::ReleaseWindow { window_id });
+
+    let navigation = manager.read_response();
+    assert_eq!(navigation.message_id, Some(navigation_id));
+    assert_error(navigation.payload);
+
+    let release = manager.read_response();
+    assert_eq!(release.message_id, Some(release_id));
+    assert!(matches!(
+        release.payload,
+        PBResponsePayload::OperationComplete
+    ));
+}
+
+#[test]
+fn cancelled_new_windows_are_released() {
+    let mut manager = Manager::start();
+    let first = manager.new_window();
+    let _second = manager.new_window();
+
+    // The pool is empty, so this waits until the cancellation arrives
+    let waiting_id = manager.send_request(PBRequestPayload::NewWindow);
+    let response = manager.request(PBRequestPayload::Cancel {
+        message_id: waiting_id,
+    });
+    assert!(matches!(response, PBResponsePayload::OperationComplete));
+
+    // This one may be handed a window before the cancellation lands
+    manager.request(PBRequestPayload::ReleaseWindow { window_id: first });
+    let racing_id = manager.send_request(PBRequestPayload::NewWindow);
+    let cancel_id = manager.send_request(PBRequestPayload::Cancel {
+        message_id: racing_id,
+    });
+    let mut handed_out = None;
+    loop {
+        let response = manager.read_response();
+        if response.message_id == Some(cancel_id) {
+            break;
+        }
+        assert_eq!(response.message_id, Some(racing_id));
+        if let PBResponsePayload::NewWindowCreated { id } = response.payload {
+            handed_out = Some(id);
+        }
+    }
+    if let Some(window_id) = handed_out {
+        manager.request(PBRequestPayload::ReleaseWindow { window_id });
+    }
+
+    // Neither cancelled request kept hold of a window
+    manager.new_window();
+    manager.assert_responsive();
+}
+
+#[test]
+fn garbage_frames_are_errors() {
+    let mut manager = Manager::start();
+
+    let frames: [&[u8]; 3] = [b"!!! not base64 !!!", b"", b"\xff\xfe\x00"];
+    for frame in frames {
+        manager.send_frame(frame);
+        let response = manager.read_response();
+        assert_eq!(response.message_id, None);
+        assert_error(response.payload);
+        manager.assert_responsive();
+    }
+
+    let messages = [
+        "{ not json",
+        r#"{"message_id": 1, "payload": {"NotARequest": null}}"#,
+        r#"{"message_id": 1, "payload": {"Navigate": {"window_id": "zero"}}}"#,
+        r#"{"message_id": null, "payload": {"Tester": "no id"}}"#,
+        r#"[1, 2, 3]"#,
+    ];
+    for message in messages {
+        manager.send_json(message);
+        let response = manager.read_response();
+        assert_eq!(
+            response.message_id, None,
+            "Unexpected response to {message}"
+        );
+        assert_error(response.payload);
+        manager.assert_responsive();
+    }
+}
+
+#[test]
+fn page_events_are_pushed_for_assigned_windows() {
+    let mut manager = Manager::start();
+    let window_id = manager.new_window();
+
+    let response = manager.request(PBRequestPayload::Navigate {
+        window_id,
+        url: "data:text/html,<script>console.warn(%22hello%22)</script>".into(),
+        wait_until: Some(WaitUntil::Load),
+    });
+    assert!(matches!(response, PBResponsePayload::NavigationComplete(_)));
+
+    let mut load_started = false;
+    loop {
+        let (event_window_id, event) = manager.next_event();
+        assert_eq!(event_window_id, window_id);
+
+        match event {
+            PageEvent::PageLoadStarted { .. } => load_started = true,
+            PageEvent::Console(message) => {
+                assert_eq!(message.level, ConsoleLevel::Warn);
+                assert_eq!(message.text, "hello");
+                break;
+            }
+            _ => {}
+        }
+    }
+    assert!(
+        load_started,
+        "The page load should be reported before its console messages"
+    );
+}
+
+#[test]
+fn socket_consumers_share_the_pool() {
+    let server = SocketManager::start();
+    let mut first = Manager::connect(&server.socket);
+    let mut second = Manager::connect(&server.socket);
+
+    // Both consumers number their requests from zero, so this checks responses are routed by connection
+    let first_window = first.new_window();
+    let second_window = second.new_window();
+    assert_ne!(first_window, second_window);
+    first.assert_responsive();
+
+    // The pool only has two windows, so this one comes from the first consumer disconnecting
+    drop(first);
+    let third_window = second.new_window();
+    assert_ne!(third_window, second_window);
+    second.assert_responsive();
+}
+
+#[test]
+fn socket_consumers_cant_use_each_others_windows() {
+    let server = SocketManager::start();
+    let mut owner = Manager::connect(&server.socket);
+    let mut other = Manager::connect(&server.socket);
+    let window_id = owner.new_window();
+
+    let requests = [
+        PBRequestPayload::Navigate {
+            window_id,
+            url: "about:blank".into(),
+            wait_until: None,
+        },
+        PBRequestPayload::GetCookies {
+            window_id,
+            url: None,
+        },
+        PBRequestPayload::ReleaseWindow { window_id },
+    ];
+
+    for request in requests {
+        let message = assert_error(other.request(request));
+        assert_eq!(
+            message,
+            format!("Window {window_id} does not exist, or has already been released")
+        );
+    }
+
+    // The window is still the owner's to use
+    let response = owner.request(PBRequestPayload::ReleaseWindow { window_id });
+    assert!(matches!(response, PBResponsePayload::OperationComplete));
+}
+
+#[test]
+fn requests_can_be_pipelined_behind_initialize() {
+    let mut manager = Manager::spawn();
+
+    // Requests switch framing straight after the Initialize request, without waiting for its response
+    let initialize_id = manager.send_request(Manager::initialize_request(Framing::LengthPrefixed));
+    manager.framing = Framing::LengthPrefixed;
+    let tester_id = manager.send_request(PBRequestPayload::Tester("pipelined".into()));
+
+    // Responses switch once Initialized has been sent
+    manager.framing = Framing::Base64;
+    let initialized = manager.read_response();
+    assert_eq!(initialized.message_id, Some(initialize_id));
+    assert!(matches!(
+        initialized.payload,
+        PBResponsePayload::Initialized {
+            framing: Framing::LengthPrefixed,
+            ..
+        }
+    ));
+
+    manager.framing = Framing::LengthPrefixed;
+    let tester = manager.read_response();
+    assert_eq!(tester.message_id, Some(tester_id));
+    assert!(matches!(tester.payload, PBResponsePayload::Tester(_)));
+    manager.assert_responsive();
+}
+
+#[test]
+fn socket_consumers_cant_have_files_written() {
+    let server = SocketManager::start();
+    let mut consumer = Manager::connect(&server.socket);
+    let window_id = consumer.new_window();
+
+    let path = std::env::temp_dir().join("pagebrowse-robustness-written.png");
+    let message = assert_error(consumer.request(PBRequestPayload::Screenshot {
+        window_id,
+        path: Some(path.display().to_string()),
+        options: Default::default(),
+    }));
+    assert!(message.contains("path"), "Unexpected error: {message}");
+    assert!(!path.exists());
+    consumer.assert_responsive();
+}
+
+#[test]
+fn running_managers_keep_their_socket() {
+    let server = SocketManager::start();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_pagebrowse_manager"))
+        .arg("--listen")
+        .arg(format!("unix:{}", server.socket.display()))
+        .status()
+        .expect("Manager should start");
+    assert!(!status.success());
+
+    let mut consumer = Manager::connect(&server.socket);
+    consumer.assert_responsive();
+}
+
+#[test]
+fn tcp_is_limited_to_loopback_addresses() {
+    let status = Command::new(env!("CARGO_BIN_EXE_pagebrowse_manager"))
+        .arg("--listen")
+        .arg("tcp:0.0.0.0:0")
+        .status()
+        .expect("Manager should start");
+    assert!(!status.success());
+}
+
+#[test]
+fn length_prefixed_frames_carry_raw_screenshots() {
+    let mut manager = Manager::start_with(Framing::LengthPrefixed);
+    let window_id = manager.new_window();
+
+    manager.request(PBRequestPayload::Navigate {
+        window_id,
+        url: "data:text/html,<h1>Hello</h1>".into(),
+        wait_until: Some(WaitUntil::Load),
+    });
+
+    let response = manager.request(PBRequestPayload::Screenshot {
+        window_id,
+        path: None,
+        options: Default::default(),
+    });
+    match response {
+        PBResponsePayload::ScreenshotCaptured { bytes } => {
+            assert!(bytes.starts_with(b"\x89PNG"), "Screenshot should be a PNG")
+        }
+        other => panic!("Expected a screenshot, got {other:?}"),
+    }
+
+    // Unparseable JSON inside a well-formed frame is an error like any other
+    manager.send_json("{ not json");
+    let response = manager.read_response();
+    assert_eq!(response.message_id, None);
+    assert_error(response.payload);
+    manager.assert_responsive();
+}
@@ -1,46 +1,351 @@
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Bumped whenever the protocol changes in a way that existing consumers or managers can't handle
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct InitializationParams {
     pub pool_size: usize,
     pub visible: bool,
     pub init_script: Option<String>,
+    /// How a released window is cleaned up before it is handed out again
+    #[serde(default)]
+    pub window_reset: WindowReset,
+    /// Give each window its own ephemeral cookies, storage and caches,
+    /// rather than sharing them across the pool
+    #[serde(default)]
+    pub isolated_sessions: bool,
+    /// Cookies and localStorage to load into every window
+    #[serde(default)]
+    pub storage_state: Option<StorageState>,
+    /// How the rest of the connection is framed. Requests use it from the one after `Initialize`,
+    /// and responses from the one after `Initialized`.
+    #[serde(default)]
+    pub framing: Framing,
+    /// The `PROTOCOL_VERSION` the consumer speaks. The manager answers either way,
+    /// so consumers should check the version in its `ManagerInfo` before carrying on.
+    #[serde(default)]
+    pub protocol_version: Option<u32>,
+}
+
+/// Describes a manager, in its answer to `Initialize`
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct ManagerInfo {
+    /// The manager's crate version
+    pub version: String,
+    pub protocol_version: u32,
+    /// e.g. `linux` or `macos`
+    pub platform: String,
+    pub capabilities: Vec<Capability>,
+}
+
+impl ManagerInfo {
+    pub fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// Features that not every manager supports, depending on its platform
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+pub enum Capability {
+    Screenshots,
+    Cookies,
+    /// Navigations report the HTTP status and any load failure
+    NavigationStatus,
+    PageEvents,
+    /// `PageEvent::Crashed` is sent when a page's process goes away
+    CrashEvents,
+    LengthPrefixedFraming,
+    /// A capability from a newer manager than this consumer knows about
+    #[serde(other)]
+    Unknown,
+}
+
+/// How messages are delimited on the wire
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Framing {
+    /// Each message is base64 encoded JSON followed by a `,`.
+    /// Every connection starts out this way, and keeps to it unless the handshake changes it.
+    #[default]
+    Base64,
+    /// Each message is its JSON length and attachment count (as big-endian `u32`s), then the JSON,
+    /// then each attachment as its length (a big-endian `u32`) and raw bytes.
+    /// Binary data such as screenshots is sent as an attachment rather than in the JSON.
+    LengthPrefixed,
+}
+
+/// How much browsing state is cleared from a window between consumers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum WindowReset {
+    /// Hand the window out again as-is
+    None,
+    /// Navigate to about:blank and clear the window's history
+    #[default]
+    Soft,
+    /// As well as a soft reset, clear all cookies, storage and caches.
+    /// Unless sessions are isolated, website data is shared by every window in the pool,
+    /// so this clears it for them too.
+    Full,
+}
+
+/// Output encoding for a screenshot
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    Jpeg,
+    WebP,
+}
+
+/// A region of the captured image, in device pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct ClipRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+pub struct ScreenshotOptions {
+    #[serde(default)]
+    pub format: ImageFormat,
+    /// Encoder quality from 0-100, used by the lossy formats (JPEG and WebP).
+    /// WebP is encoded losslessly if no quality is given.
+    pub quality: Option<u8>,
+    /// Crop the captured image to this region
+    pub clip: Option<ClipRect>,
+    /// Capture the full document rather than only the visible viewport
+    #[serde(default)]
+    pub full_page: bool,
+}
+
+/// The point in a page load at which a navigation is considered complete
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum WaitUntil {
+    /// The HTML document has been parsed, without waiting for images or stylesheets
+    DomContentLoaded,
+    /// The page and all of its subresources have loaded
+    Load,
+    /// The page has loaded, and then made no network requests for this many milliseconds
+    NetworkIdle(u64),
+    /// An element matching this CSS selector exists in the document
+    Selector(String),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct NavigationResult {
+    /// The URL the webview ended up on, after any redirects.
+    /// If the navigation wasn't waited on, this is the requested URL.
+    pub final_url: String,
+    /// HTTP status of the main resource, if the platform reports one
+    pub status: Option<u16>,
+    pub mime_type: Option<String>,
+    /// Why the page failed to load, e.g. a DNS or TLS failure
+    pub error: Option<String>,
+}
+
+impl NavigationResult {
+    /// Whether the page loaded without an error or an HTTP error status
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none() && !matches!(self.status, Some(status) if status >= 400)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum SameSite {
+    None,
+    Lax,
+    Strict,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// A leading `.` makes the cookie apply to subdomains too
+    pub domain: String,
+    pub path: String,
+    /// Seconds since the Unix epoch, or `None` for a session cookie
+    #[serde(default)]
+    pub expires: Option<i64>,
+    #[serde(default)]
+    pub http_only: bool,
+    #[serde(default)]
+    pub secure: bool,
+    #[serde(default)]
+    pub same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// A session cookie for every path on `domain`
+    pub fn new(
+        name: impl Into<String>,
+        value: impl Into<String>,
+        domain: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            domain: domain.into(),
+            path: "/".into(),
+            expires: None,
+            http_only: false,
+            secure: false,
+            same_site: None,
+        }
+    }
+}
+
+/// A window's cookies and localStorage, which can be saved and loaded into other windows
+/// to reuse a logged in session
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct StorageState {
+    pub cookies: Vec<Cookie>,
+    /// The localStorage of each origin. When captured from a window,
+    /// this only holds the origin of the page it was on.
+    pub origins: Vec<OriginStorage>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct OriginStorage {
+    /// e.g. `https://example.com`
+    pub origin: String,
+    pub local_storage: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+pub enum ConsoleLevel {
+    Debug,
+    Log,
+    Info,
+    Warn,
+    Error,
+}
+
+/// A `console.*` call made by a page
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+pub struct ConsoleMessage {
+    pub level: ConsoleLevel,
+    /// The logged values, formatted and joined by spaces
+    pub text: String,
+    /// The script that logged the message, if the platform's stack traces show it
+    pub source_url: Option<String>,
+    pub line: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+pub enum DialogKind {
+    Alert,
+    Confirm,
+    Prompt,
+}
+
+/// Something that happened in a window's page, pushed to the client as it happens
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+pub enum PageEvent {
+    PageLoadStarted {
+        url: String,
+    },
+    PageLoadFinished {
+        url: String,
+    },
+    /// The page changed its URL without loading a new page,
+    /// e.g. through the history API or by following a fragment link
+    UrlChanged {
+        url: String,
+    },
+    Console(ConsoleMessage),
+    /// An exception thrown by the page that nothing caught, or a promise rejection that nothing handled
+    PageError {
+        message: String,
+        source_url: Option<String>,
+        line: Option<u32>,
+        column: Option<u32>,
+        stack: Option<String>,
+    },
+    /// The page opened a dialog
+    Dialog {
+        kind: DialogKind,
+        message: String,
+    },
+    /// The process rendering the page has gone away.
+    /// The window won't respond to scripts until it navigates again.
+    Crashed {
+        reason: String,
+    },
 }
 
 mod requests {
     use super::*;
 
-    #[derive(Debug, Deserialize, Serialize)]
+    /// A message from a consumer to the manager
+    #[derive(Debug, Deserialize, Serialize, JsonSchema)]
     pub struct PBRequest {
         pub message_id: Option<u32>,
         pub payload: PBRequestPayload,
     }
 
-    #[derive(Debug, Serialize, Deserialize)]
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
     pub enum PBRequestPayload {
         Tester(String),
         Initialize(InitializationParams),
         NewWindow,
+        /// Stops waiting on an earlier request. A response that can't be withdrawn
+        /// (e.g. a script that is already running) may still be sent for it.
+        Cancel {
+            message_id: u32,
+        },
         ReleaseWindow {
             window_id: u32,
         },
+        /// Loads `url`, responding once the `wait_until` condition holds,
+        /// or immediately if there is nothing to wait for
         Navigate {
             window_id: u32,
             url: String,
-            wait_for_load: bool,
+            wait_until: Option<WaitUntil>,
         },
         ResizeWindow {
             window_id: u32,
             width: usize,
             height: usize,
         },
+        /// Runs `script` as the body of an async function.
+        /// Each of the `arguments` is available to the script as a local variable.
         EvaluateScript {
             window_id: u32,
             script: String,
+            #[serde(default)]
+            arguments: serde_json::Map<String, serde_json::Value>,
         },
+        /// Returns the cookies that would be sent with a request to `url`,
+        /// or every cookie visible to the window if no `url` is given.
+        /// On Linux, that misses cookies only sent to a subdomain or path other than the current page's.
+        GetCookies {
+            window_id: u32,
+            url: Option<String>,
+        },
+        SetCookies {
+            window_id: u32,
+            cookies: Vec<Cookie>,
+        },
+        /// Deletes every cookie visible to the window, which
+        /// unless sessions are isolated is every cookie in the pool
+        ClearCookies {
+            window_id: u32,
+        },
+        /// Captures the window, writing it to `path` if provided,
+        /// or otherwise returning the bytes in a `ScreenshotCaptured` response.
+        /// Consumers connected over a socket can't provide a `path`.
         Screenshot {
             window_id: u32,
-            path: String,
+            path: Option<String>,
+            #[serde(default)]
+            options: ScreenshotOptions,
         },
     }
 }
@@ -48,27 +353,176 @@ mod requests {
 mod responses {
     use super::*;
 
-    #[derive(Clone, Debug, Deserialize, Serialize)]
+    /// A message from the manager, either answering a request or pushed without a `message_id`
+    #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
     pub struct PBResponse {
         pub message_id: Option<u32>,
         pub payload: PBResponsePayload,
     }
 
-    #[derive(Clone, Debug, Serialize, Deserialize)]
+    #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
     pub enum PBResponsePayload {
         Error {
             original_message: Option<String>,
             message: String,
         },
         Tester(String),
+        /// Answers `Initialize`, confirming the framing used from the next message on
+        Initialized {
+            framing: Framing,
+            manager: ManagerInfo,
+        },
         NewWindowCreated {
             id: u32,
         },
+        NavigationComplete(NavigationResult),
         ScriptEvaluated {
             output: String,
         },
+        /// The evaluated script threw, or returned a rejected promise
+        ScriptError {
+            message: String,
+            line: Option<u32>,
+            column: Option<u32>,
+            stack: Option<String>,
+        },
+        /// With length-prefixed framing, the bytes are sent as the frame's attachment
+        ScreenshotCaptured {
+            /// Base64 encoded, or empty if the bytes were sent as an attachment
+            #[serde(with = "base64_bytes")]
+            #[schemars(with = "String")]
+            bytes: Vec<u8>,
+        },
+        Cookies {
+            cookies: Vec<Cookie>,
+        },
+        /// Sent without a message ID whenever something happens in an assigned window's page
+        PageEvent {
+            window_id: u32,
+            event: PageEvent,
+        },
         OperationComplete,
     }
+
+    impl PBResponse {
+        /// Moves any binary data out of the response, to be sent after the JSON
+        /// as length-prefixed attachments
+        pub fn take_attachments(&mut self) -> Vec<Vec<u8>> {
+            match &mut self.payload {
+                PBResponsePayload::ScreenshotCaptured { bytes } => vec![std::mem::take(bytes)],
+                _ => vec![],
+            }
+        }
+
+        /// Puts the attachments that arrived with the response back in place
+        pub fn restore_attachments(&mut self, attachments: Vec<Vec<u8>>) -> Result<(), String> {
+            let mut attachments = attachments.into_iter();
+
+            if let PBResponsePayload::ScreenshotCaptured { bytes } = &mut self.payload {
+                *bytes = attachments
+                    .next()
+                    .ok_or("Screenshot response is missing its attachment")?;
+            }
+
+            match attachments.next() {
+                Some(_) => Err("Response has more attachments than it can hold".into()),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+/// A consumer's end of an in-memory connection to a manager hosted in the same process.
+/// Messages are passed over the channels as they are, so there's no framing to agree on.
+pub struct InProcessConnection {
+    pub requests: std::sync::mpsc::Sender<PBRequest>,
+    pub responses: std::sync::mpsc::Receiver<PBResponse>,
+}
+
+/// Encoding and decoding helpers for the `Framing` modes
+pub mod framing {
+    use base64::{engine::general_purpose, Engine};
+
+    /// The length of the JSON and the number of attachments that start a length-prefixed frame
+    pub const HEADER_LEN: usize = 8;
+    /// Larger lengths are treated as garbage rather than allocated
+    pub const MAX_PART_LEN: usize = 1 << 30;
+    pub const MAX_ATTACHMENTS: usize = 16;
+
+    pub fn encode_base64(json: &[u8]) -> Vec<u8> {
+        let mut frame = general_purpose::STANDARD.encode(json).into_bytes();
+        frame.push(b',');
+        frame
+    }
+
+    pub fn encode_length_prefixed(json: &[u8], attachments: &[Vec<u8>]) -> Vec<u8> {
+        let attachments_len: usize = attachments.iter().map(|a| 4 + a.len()).sum();
+        let mut frame = Vec::with_capacity(HEADER_LEN + json.len() + attachments_len);
+
+        frame.extend_from_slice(&(json.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&(attachments.len() as u32).to_be_bytes());
+        frame.extend_from_slice(json);
+        for attachment in attachments {
+            frame.extend_from_slice(&(attachment.len() as u32).to_be_bytes());
+            frame.extend_from_slice(attachment);
+        }
+
+        frame
+    }
+
+    /// Returns the JSON length and attachment count from a frame header
+    pub fn parse_header(header: [u8; HEADER_LEN]) -> Result<(usize, usize), String> {
+        let [a, b, c, d, e, f, g, h] = header;
+        let json_len = parse_length([a, b, c, d])?;
+        let attachments = u32::from_be_bytes([e, f, g, h]) as usize;
+
+        if attachments > MAX_ATTACHMENTS {
+            return Err(format!("Frame claims to have {attachments} attachments"));
+        }
+
+        Ok((json_len, attachments))
+    }
+
+    pub fn parse_length(prefix: [u8; 4]) -> Result<usize, String> {
+        let len = u32::from_be_bytes(prefix) as usize;
+        if len > MAX_PART_LEN {
+            return Err(format!("Frame claims to be {len} bytes long"));
+        }
+        Ok(len)
+    }
+}
+
+/// JSON Schemas for the messages sent each way, for consumers that aren't written in Rust.
+/// Published copies are kept in this crate's `schema` directory.
+pub mod schema {
+    use super::{PBRequest, PBResponse};
+
+    /// Describes the `PBRequest` messages a consumer sends to the manager
+    pub fn requests() -> serde_json::Value {
+        serde_json::to_value(schemars::schema_for!(PBRequest)).expect("Schemas serialize")
+    }
+
+    /// Describes the `PBResponse` messages the manager sends back, including pushed page events
+    pub fn responses() -> serde_json::Value {
+        serde_json::to_value(schemars::schema_for!(PBResponse)).expect("Schemas serialize")
+    }
+}
+
+/// Carries binary data as a base64 string rather than a JSON array of numbers
+mod base64_bytes {
+    use base64::{engine::general_purpose, Engine};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&general_purpose::STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(serde::de::Error::custom)
+    }
 }
 
 pub use requests::*;
@@ -0,0 +1,302 @@
+//! Checks that the published schemas and example messages in `schema/` describe
+//! what this crate actually sends and accepts.
+//!
+//! Run with `PAGEBROWSE_UPDATE_SCHEMA=1` to rewrite the published schemas after changing the types.
+
+use std::{collections::BTreeSet, path::PathBuf};
+
+use jsonschema::JSONSchema;
+use pagebrowse_types::{
+    framing, schema, ImageFormat, PBRequest, PBRequestPayload, PBResponse, PBResponsePayload,
+    PageEvent,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+fn schema_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("schema")
+}
+
+fn read_json(name: &str) -> Value {
+    let path = schema_dir().join(name);
+    let contents = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {e}", path.display()));
+    serde_json::from_str(&contents)
+        .unwrap_or_else(|e| panic!("Failed to parse {}: {e}", path.display()))
+}
+
+fn examples(name: &str) -> Vec<Value> {
+    match read_json(&format!("examples/{name}")) {
+        Value::Array(examples) => examples,
+        other => panic!("Expected {name} to be an array, got {other}"),
+    }
+}
+
+fn compile(schema: &Value) -> JSONSchema {
+    JSONSchema::compile(schema).expect("Generated schema should be valid")
+}
+
+fn assert_valid(schema: &JSONSchema, message: &Value) {
+    if let Err(errors) = schema.validate(message) {
+        let errors: Vec<String> = errors
+            .map(|e| format!("{e} at {}", e.instance_path))
+            .collect();
+        panic!("{message} doesn't match the schema:\n{}", errors.join("\n"));
+    }
+}
+
+/// Parses a message and serializes it again, expecting exactly what was parsed
+fn assert_round_trips<T: DeserializeOwned + Serialize>(message: &Value) -> T {
+    let parsed: T = serde_json::from_value(message.clone())
+        .unwrap_or_else(|e| panic!("Failed to parse {message}: {e}"));
+    let serialized = serde_json::to_value(&parsed).expect("Messages serialize");
+    assert_eq!(&serialized, message, "Message changed in a round trip");
+    parsed
+}
+
+fn check_published(name: &str, generated: Value) {
+    let path = schema_dir().join(name);
+
+    if std::env::var_os("PAGEBROWSE_UPDATE_SCHEMA").is_some() {
+        let mut contents = serde_json::to_string_pretty(&generated).unwrap();
+        contents.push('\n');
+        std::fs::write(&path, contents).unwrap();
+        return;
+    }
+
+    assert!(
+        read_json(name) == generated,
+        "{} is out of date, rerun with PAGEBROWSE_UPDATE_SCHEMA=1 to update it",
+        path.display()
+    );
+}
+
+// Exhaustive, so that adding a variant fails to compile until it's given an example
+
+fn request_variant(payload: &PBRequestPayload) -> &'static str {
+    match payload {
+        PBRequestPayload::Tester(_) => "Tester",
+        PBRequestPayload::Initialize(_) => "Initialize",
+        PBRequestPayload::NewWindow => "NewWindow",
+        PBRequestPayload::Cancel { .. } => "Cancel",
+        PBRequestPayload::ReleaseWindow { .. } => "ReleaseWindow",
+        PBRequestPayload::Navigate { .. } => "Navigate",
+        PBRequestPayload::ResizeWindow { .. } => "ResizeWindow",
+        PBRequestPayload::EvaluateScript { .. } => "EvaluateScript",
+        PBRequestPayload::GetCookies { .. } => "GetCookies",
+        PBRequestPayload::SetCookies { .. } => "SetCookies",
+        PBRequestPayload::ClearCookies { .. } => "ClearCookies",
+        PBRequestPayload::Screenshot { .. } => "Screenshot",
+    }
+}
+
+fn response_variant(payload: &PBResponsePayload) -> &'static str {
+    match payload {
+        PBResponsePayload::Error { .. } => "Error",
+        PBResponsePayload::Tester(_) => "Tester",
+        PBResponsePayload::Initialized { .. } => "Initialized",
+        PBResponsePayload::NewWindowCreated { .. } => "NewWindowCreated",
+        PBResponsePayload::NavigationComplete(_) => "NavigationComplete",
+        PBResponsePayload::ScriptEvaluated { .. } => "ScriptEvaluated",
+        PBResponsePayload::ScriptError { .. } => "ScriptError",
+        PBResponsePayload::ScreenshotCaptured { .. } => "ScreenshotCaptured",
+        PBResponsePayload::Cookies { .. } => "Cookies",
+        PBResponsePayload::PageEvent { event, .. } => match event {
+            PageEvent::PageLoadStarted { .. } => "PageEvent::PageLoadStarted",
+            PageEvent::PageLoadFinished { .. } => "PageEvent::PageLoadFinished",
+            PageEvent::UrlChanged { .. } => "PageEvent::UrlChanged",
+            PageEvent::Console(_) => "PageEvent::Console",
+            PageEvent::PageError { .. } => "PageEvent::PageError",
+            PageEvent::Dialog { .. } => "PageEvent::Dialog",
+            PageEvent::Crashed { .. } => "PageEvent::Crashed",
+        },
+        PBResponsePayload::OperationComplete => "OperationComplete",
+    }
+}
+
+#[test]
+fn published_schemas_are_up_to_date() {
+    check_published("requests.schema.json", schema::requests());
+    check_published("responses.schema.json", schema::responses());
+}
+
+#[test]
+fn example_requests_round_trip() {
+    let schema = compile(&schema::requests());
+    let mut covered = BTreeSet::new();
+
+    for example in examples("requests.json") {
+        assert_valid(&schema, &example);
+        let request: PBRequest = assert_round_trips(&example);
+        covered.insert(request_variant(&request.payload));
+    }
+
+    let expected = [
+        "Tester",
+        "Initialize",
+        "NewWindow",
+        "Cancel",
+        "ReleaseWindow",
+        "Navigate",
+        "ResizeWindow",
+        "EvaluateScript",
+        "GetCookies",
+        "SetCookies",
+        "ClearCookies",
+        "Screenshot",
+    ];
+    assert_eq!(covered, BTreeSet::from(expected));
+}
+
+#[test]
+fn example_responses_round_trip() {
+    let schema = compile(&schema::responses());
+    let mut covered = BTreeSet::new();
+
+    for example in examples("responses.json") {
+        assert_valid(&schema, &example);
+        let response: PBResponse = assert_round_trips(&example);
+        covered.insert(response_variant(&response.payload));
+    }
+
+    let expected = [
+        "Error",
+        "Tester",
+        "Initialized",
+        "NewWindowCreated",
+        "NavigationComplete",
+        "ScriptEvaluated",
+        "ScriptError",
+        "ScreenshotCaptured",
+        "Cookies",
+        "PageEvent::PageLoadStarted",
+        "PageEvent::PageLoadFinished",
+        "PageEvent::UrlChanged",
+        "PageEvent::Console",
+        "PageEvent::PageError",
+        "PageEvent::Dialog",
+        "PageEvent::Crashed",
+        "OperationComplete",
+    ];
+    assert_eq!(covered, BTreeSet::from(expected));
+}
+
+#[test]
+fn defaulted_fields_can_be_left_out() {
+    let schema = compile(&schema::requests());
+    let request = json!({
+        "message_id": 0,
+        "payload": { "Initialize": { "pool_size": 1, "visible": false, "init_script": null } }
+    });
+
+    assert_valid(&schema, &request);
+    let parsed: PBRequest = serde_json::from_value(request).unwrap();
+    let PBRequestPayload::Initialize(params) = parsed.payload else {
+        panic!("Expected an Initialize request, got {:?}", parsed.payload);
+    };
+    assert_eq!(params.protocol_version, None);
+}
+
+#[test]
+fn screenshot_options_can_be_left_out() {
+    let schema = compile(&schema::requests());
+    let request = json!({
+        "message_id": 0,
+        "payload": { "Screenshot": { "window_id": 0, "path": null, "options": {} } }
+    });
+
+    assert_valid(&schema, &request);
+    let parsed: PBRequest = serde_json::from_value(request).unwrap();
+    let PBRequestPayload::Screenshot { options, .. } = parsed.payload else {
+        panic!("Expected a Screenshot request, got {:?}", parsed.payload);
+    };
+    assert_eq!(options.format, ImageFormat::Png);
+    assert!(!options.full_page);
+}
+
+#[test]
+fn schemas_reject_malformed_messages() {
+    let requests = compile(&schema::requests());
+    let responses = compile(&schema::responses());
+
+    let malformed_requests = [
+        json!({ "message_id": 0 }),
+        json!({ "message_id": 0, "payload": "Explode" }),
+        json!({ "message_id": "0", "payload": "NewWindow" }),
+        json!({ "message_id": 0, "payload": { "ReleaseWindow": {} } }),
+        json!({ "message_id": 0, "payload": { "Navigate": { "window_id": -1, "url": "/" } } }),
+    ];
+    for request in malformed_requests {
+        assert!(!requests.is_valid(&request), "Schema accepted {request}");
+        assert!(serde_json::from_value::<PBRequest>(request).is_err());
+    }
+
+    let malformed_responses = [
+        json!({ "message_id": 0, "payload": { "NewWindowCreated": { "id": "zero" } } }),
+        json!({ "message_id": 0, "payload": { "ScreenshotCaptured": { "bytes": [1, 2] } } }),
+    ];
+    for response in malformed_responses {
+        assert!(!responses.is_valid(&response), "Schema accepted {response}");
+        assert!(serde_json::from_value::<PBResponse>(response).is_err());
+    }
+}
+
+#[test]
+fn base64_frames_round_trip() {
+    let json = br#"{"message_id":0,"payload":"NewWindow"}"#;
+    let frame = framing::encode_base64(json);
+
+    assert_eq!(frame.last(), Some(&b','));
+    assert_eq!(
+        &frame[..frame.len() - 1],
+        b"eyJtZXNzYWdlX2lkIjowLCJwYXlsb2FkIjoiTmV3V2luZG93In0="
+    );
+}
+
+#[test]
+fn length_prefixed_frames_carry_attachments() {
+    let mut response = PBResponse {
+        message_id: Some(3),
+        payload: PBResponsePayload::ScreenshotCaptured {
+            bytes: vec![0x89, b'P', b'N', b'G'],
+        },
+    };
+    let attachments = response.take_attachments();
+    let json = serde_json::to_vec(&response).unwrap();
+    let frame = framing::encode_length_prefixed(&json, &attachments);
+
+    let header: [u8; framing::HEADER_LEN] = frame[..framing::HEADER_LEN].try_into().unwrap();
+    let (json_len, attachment_count) = framing::parse_header(header).unwrap();
+    assert_eq!((json_len, attachment_count), (json.len(), 1));
+
+    let rest = &frame[framing::HEADER_LEN..];
+    let (body, rest) = rest.split_at(json_len);
+    let attachment_len = framing::parse_length(rest[..4].try_into().unwrap()).unwrap();
+    assert_eq!(&rest[4..], &[0x89, b'P', b'N', b'G']);
+    assert_eq!(attachment_len, 4);
+
+    // The bytes are left out of the JSON, and put back from the attachment
+    let mut parsed: PBResponse = serde_json::from_slice(body).unwrap();
+    assert!(matches!(
+        &parsed.payload,
+        PBResponsePayload::ScreenshotCaptured { bytes } if bytes.is_empty()
+    ));
+    parsed
+        .restore_attachments(vec![rest[4..].to_vec()])
+        .unwrap();
+    assert_eq!(
+        serde_json::to_value(&parsed).unwrap(),
+        json!({ "message_id": 3, "payload": { "ScreenshotCaptured": { "bytes": "iVBORw==" } } })
+    );
+}
+
+#[test]
+fn oversized_frames_are_rejected() {
+    let mut header = [0; framing::HEADER_LEN];
+    header[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert!(framing::parse_header(header).is_err());
+
+    let mut header = [0; framing::HEADER_LEN];
+    header[4..].copy_from_slice(&(framing::MAX_ATTACHMENTS as u32 + 1).to_be_bytes());
+    assert!(framing::parse_header(header).is_err());
+}